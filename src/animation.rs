@@ -0,0 +1,281 @@
+use super::callbacks;
+use super::{Image, ImageSource};
+
+use std::cmp;
+use std::time::{Duration, Instant};
+
+/// Playback state of animated content. The backends only show the frame it points to and wake it up with their
+/// main-loop timers, once `next_delay` has passed.
+pub struct Player {
+    delays: Vec<Duration>,
+    frame: usize,
+    playing: bool,
+    visible: bool,
+    loop_count: Option<u32>,
+    loops: u32,
+    due: Option<Instant>,
+    pub on_frame: Option<callbacks::Frame>,
+}
+
+impl Player {
+    pub fn new(source: Option<&ImageSource>) -> Player {
+        let mut player = Player {
+            delays: Vec::new(),
+            frame: 0,
+            playing: true,
+            visible: true,
+            loop_count: None,
+            loops: 0,
+            due: None,
+            on_frame: None,
+        };
+        player.reset(source);
+        player
+    }
+    /// Starts over from the first frame of `source`, which is not animated if it is `None` or still content.
+    pub fn reset(&mut self, source: Option<&ImageSource>) {
+        self.delays = match source {
+            Some(source) => (0..source.frame_count()).filter_map(|i| source.frame_delay(i)).collect(),
+            None => Vec::new(),
+        };
+        self.frame = 0;
+        self.loops = 0;
+        self.restart();
+    }
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+    /// Starts over if the last loop is over. Does nothing with a loop count of 0.
+    pub fn play(&mut self) {
+        if !self.playing && self.loop_count != Some(0) {
+            if self.is_finished() {
+                self.frame = 0;
+                self.loops = 0;
+            }
+            self.playing = true;
+            self.restart();
+        }
+    }
+    pub fn pause(&mut self) {
+        self.playing = false;
+        self.restart();
+    }
+    pub fn seek(&mut self, frame: usize) {
+        self.frame = cmp::min(frame, cmp::max(1, self.delays.len()) - 1);
+        self.restart();
+    }
+    pub fn loop_count(&self) -> Option<u32> {
+        self.loop_count
+    }
+    /// `Some(0)` shows the first frame without playing. A count the animation has not reached yet plays it on
+    /// from the first frame if the count before had stopped it.
+    pub fn set_loop_count(&mut self, count: Option<u32>) {
+        let finished = !self.playing && self.is_finished();
+        self.loop_count = count;
+        if count == Some(0) {
+            self.frame = 0;
+            self.loops = 0;
+            self.playing = false;
+        } else if finished && !self.is_finished() {
+            self.frame = 0;
+            self.playing = true;
+        }
+        self.restart();
+    }
+    /// Frames do not advance while the image is not visible.
+    pub fn set_visible(&mut self, visible: bool) {
+        if self.visible != visible {
+            self.visible = visible;
+            self.restart();
+        }
+    }
+    /// Moves on to the next frame if the current one has been shown for its delay, returning whether it did.
+    /// Stops playing on the last frame once the last loop is over.
+    pub fn advance(&mut self, now: Instant) -> bool {
+        match self.due {
+            Some(due) if due <= now => {}
+            _ => return false,
+        }
+        if self.frame + 1 < self.delays.len() {
+            self.frame += 1;
+        } else {
+            self.loops += 1;
+            if self.is_finished() {
+                self.playing = false;
+                self.due = None;
+                return false;
+            }
+            self.frame = 0;
+        }
+        self.due = Some(now + self.delays[self.frame]);
+        true
+    }
+    /// Time left until the next frame is due, `None` if the frames are not advancing.
+    pub fn next_delay(&self, now: Instant) -> Option<Duration> {
+        self.due.map(|due| if due > now { due - now } else { Duration::from_millis(0) })
+    }
+    /// Shows the current frame for its full delay from now on.
+    fn restart(&mut self) {
+        self.due = if self.playing && self.visible && self.delays.len() > 1 {
+            Some(Instant::now() + self.delays[self.frame])
+        } else {
+            None
+        };
+    }
+    fn is_finished(&self) -> bool {
+        self.loop_count.map_or(false, |count| self.loops >= count)
+    }
+}
+
+/// Fires the `on_frame` callback of `player`, which is expected to belong to `image`.
+pub fn notify(image: &mut Image, player: &mut Player) {
+    let frame = player.frame;
+    if let Some(ref mut cb) = player.on_frame {
+        (cb.as_mut())(image, frame);
+    }
+}
+
+/// `delay` in whole milliseconds, rounded up, so the timers never wake up before the frame is due.
+pub fn millis(delay: Duration) -> u32 {
+    (delay.as_secs() * 1000 + (delay.subsec_nanos() as u64 + 999_999) / 1_000_000) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::RgbaImage;
+
+    /// Animated content of `delays` in milliseconds.
+    fn animated(delays: &[u64]) -> ImageSource {
+        ImageSource::animated(delays.iter().map(|&delay| (RgbaImage::new(1, 1), Duration::from_millis(delay))).collect())
+    }
+    /// Advances `player` at the time its frame is due, returning the frame shown then.
+    fn step(player: &mut Player) -> Option<usize> {
+        let due = player.due?;
+        assert!(!player.advance(due - Duration::from_millis(1)));
+        if player.advance(due) {
+            Some(player.frame())
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn loops() {
+        let mut player = Player::new(Some(&animated(&[10, 20, 30])));
+        assert_eq!(player.frame(), 0);
+        let due = player.due.unwrap();
+        assert!(player.advance(due));
+        // each frame is due its own delay after the one before
+        assert_eq!(player.due, Some(due + Duration::from_millis(20)));
+        assert_eq!(player.next_delay(due), Some(Duration::from_millis(20)));
+        assert_eq!(player.next_delay(due + Duration::from_secs(1)), Some(Duration::from_millis(0)));
+        assert_eq!(step(&mut player), Some(2));
+        // over the loop boundary, forever by default
+        for _ in 0..3 {
+            assert_eq!(step(&mut player), Some(0));
+            assert_eq!(step(&mut player), Some(1));
+            assert_eq!(step(&mut player), Some(2));
+        }
+        assert!(player.is_playing());
+    }
+
+    #[test]
+    fn finishes_on_last_frame() {
+        let mut player = Player::new(Some(&animated(&[10, 10])));
+        player.set_loop_count(Some(2));
+        assert_eq!(step(&mut player), Some(1));
+        assert_eq!(step(&mut player), Some(0));
+        assert_eq!(step(&mut player), Some(1));
+        assert_eq!(step(&mut player), None);
+        assert_eq!(player.frame(), 1);
+        assert!(!player.is_playing());
+        assert_eq!(player.due, None);
+
+        // playing again starts over
+        player.play();
+        assert!(player.is_playing());
+        assert_eq!(player.frame(), 0);
+        assert_eq!(step(&mut player), Some(1));
+        assert_eq!(step(&mut player), Some(0));
+        assert_eq!(step(&mut player), Some(1));
+        assert_eq!(step(&mut player), None);
+
+        // and so does raising the count, for the loops not played yet
+        player.set_loop_count(Some(3));
+        assert!(player.is_playing());
+        assert_eq!(player.frame(), 0);
+        assert_eq!(step(&mut player), Some(1));
+        assert_eq!(step(&mut player), None);
+    }
+
+    #[test]
+    fn no_loops() {
+        let mut player = Player::new(Some(&animated(&[10, 10, 10])));
+        player.seek(2);
+        player.set_loop_count(Some(0));
+        assert_eq!(player.frame(), 0);
+        assert!(!player.is_playing());
+        assert_eq!(player.due, None);
+        player.play();
+        assert!(!player.is_playing());
+        assert_eq!(player.due, None);
+
+        // any other count plays it
+        player.set_loop_count(None);
+        assert!(player.is_playing());
+        assert_eq!(step(&mut player), Some(1));
+    }
+
+    #[test]
+    fn seek_and_pause() {
+        let mut player = Player::new(Some(&animated(&[10, 20, 30])));
+        player.seek(1);
+        assert_eq!(player.frame(), 1);
+        player.seek(7);
+        assert_eq!(player.frame(), 2);
+        assert_eq!(step(&mut player), Some(0));
+
+        player.pause();
+        assert!(!player.is_playing());
+        assert_eq!(player.due, None);
+        assert!(!player.advance(Instant::now() + Duration::from_secs(60)));
+        // seeking while paused shows the frame without playing on
+        player.seek(1);
+        assert_eq!(player.due, None);
+        player.play();
+        assert_eq!(player.frame(), 1);
+        assert_eq!(step(&mut player), Some(2));
+    }
+
+    #[test]
+    fn hidden() {
+        let mut player = Player::new(Some(&animated(&[10, 20])));
+        player.set_visible(false);
+        assert_eq!(player.due, None);
+        assert!(player.is_playing());
+        assert!(!player.advance(Instant::now() + Duration::from_secs(60)));
+        assert_eq!(player.frame(), 0);
+        // shown again, the frame gets its full delay
+        let before = Instant::now();
+        player.set_visible(true);
+        assert!(player.due.unwrap() >= before + Duration::from_millis(10));
+        assert_eq!(step(&mut player), Some(1));
+    }
+
+    #[test]
+    fn still() {
+        for source in &[None, Some(ImageSource::new(RgbaImage::new(1, 1))), Some(animated(&[10]))] {
+            let mut player = Player::new(source.as_ref());
+            assert_eq!(player.due, None);
+            assert!(!player.advance(Instant::now() + Duration::from_secs(60)));
+            player.seek(3);
+            assert_eq!(player.frame(), 0);
+            assert_eq!(player.next_delay(Instant::now()), None);
+        }
+    }
+}
@@ -0,0 +1,386 @@
+//! Named regions of a sprite sheet, so any number of images can show its parts sharing one decoded buffer.
+//!
+//! The regions are read from a JSON description, either in the TexturePacker format, hash or array flavour:
+//!
+//! ```json
+//! { "frames": { "play.png": { "frame": { "x": 0, "y": 0, "w": 16, "h": 16 }, "rotated": false } } }
+//! { "frames": [ { "filename": "play.png", "frame": { "x": 0, "y": 0, "w": 16, "h": 16 } } ] }
+//! ```
+//!
+//! or as a plain map of names to rectangles:
+//!
+//! ```json
+//! { "play": { "x": 0, "y": 0, "w": 16, "h": 16 } }
+//! ```
+//!
+//! The regions TexturePacker trimmed the transparent edges off are shown in place within the whole sprite, as given by
+//! `spriteSourceSize` and `sourceSize`. Rotated regions are not supported.
+
+use super::{ImageError, ImageSource, Region, Trim};
+
+use std::collections::HashMap;
+use std::{char, str};
+
+pub struct Atlas {
+    source: ImageSource,
+    regions: HashMap<String, Region>,
+}
+
+impl Atlas {
+    /// Sprite sheet without any named regions yet.
+    pub fn new(source: ImageSource) -> Atlas {
+        Atlas {
+            source: source,
+            regions: HashMap::new(),
+        }
+    }
+    pub fn from_json(source: ImageSource, json: &str) -> Result<Atlas, ImageError> {
+        let mut atlas = Atlas::new(source);
+        let root = Parser::new(json).parse()?;
+        match root.get("frames") {
+            Some(&Json::Object(ref frames)) => {
+                for &(ref name, ref frame) in frames {
+                    atlas.insert_frame(name, frame)?;
+                }
+            }
+            Some(&Json::Array(ref frames)) => {
+                for frame in frames {
+                    let name = match frame.get("filename") {
+                        Some(&Json::String(ref name)) => name,
+                        _ => return Err(invalid("frame without a filename")),
+                    };
+                    atlas.insert_frame(name, frame)?;
+                }
+            }
+            Some(_) => return Err(invalid("frames are neither an object nor an array")),
+            None => match root {
+                Json::Object(ref regions) => {
+                    for &(ref name, ref rect) in regions {
+                        let region = region(name, rect)?.ok_or_else(|| invalid(&format!("region `{}` is not a rectangle", name)))?;
+                        atlas.insert(name.clone(), region);
+                    }
+                }
+                _ => return Err(invalid("description is not an object")),
+            },
+        }
+        Ok(atlas)
+    }
+    pub fn insert(&mut self, name: String, region: Region) {
+        self.regions.insert(name, region);
+    }
+    pub fn source(&self) -> &ImageSource {
+        &self.source
+    }
+    pub fn region(&self, name: &str) -> Option<Region> {
+        self.regions.get(name).cloned()
+    }
+    pub fn regions(&self) -> &HashMap<String, Region> {
+        &self.regions
+    }
+
+    fn insert_frame(&mut self, name: &str, frame: &Json) -> Result<(), ImageError> {
+        if let Some(&Json::Bool(true)) = frame.get("rotated") {
+            return Err(invalid(&format!("region `{}` is rotated, which is not supported", name)));
+        }
+        let rect = frame.get("frame").map_or(Ok(None), |rect| region(name, rect))?;
+        let mut region = rect.ok_or_else(|| invalid(&format!("region `{}` has no frame rectangle", name)))?;
+        if let Some(&Json::Bool(true)) = frame.get("trimmed") {
+            let within = frame.get("spriteSourceSize").map_or(Ok(None), |rect| self::region(name, rect))?;
+            let sprite = match frame.get("sourceSize") {
+                Some(size) => (number(name, size, &["w", "width"])?, number(name, size, &["h", "height"])?),
+                None => (None, None),
+            };
+            let trim = match (within, sprite) {
+                (Some(within), (Some(width), Some(height))) => Trim { x: within.x, y: within.y, width: width, height: height },
+                _ => return Err(invalid(&format!("region `{}` is trimmed without its sprite size", name))),
+            };
+            region = region.with_trim(trim);
+        }
+        self.insert(name.to_owned(), region);
+        Ok(())
+    }
+}
+
+/// The rectangle `rect` of the region `name`, `None` if it lacks a coordinate.
+fn region(name: &str, rect: &Json) -> Result<Option<Region>, ImageError> {
+    let coordinates = (number(name, rect, &["x"])?, number(name, rect, &["y"])?, number(name, rect, &["w", "width"])?, number(name, rect, &["h", "height"])?);
+    Ok(match coordinates {
+        (Some(x), Some(y), Some(width), Some(height)) => Some(Region::new(x, y, width, height)),
+        _ => None,
+    })
+}
+
+/// The first of the `names` members of `object`, `None` if it has none that is a number.
+///
+/// A number that is not a whole count of pixels within `u32` is an error, rounding it would cut another rectangle than the one described.
+fn number(name: &str, object: &Json, names: &[&str]) -> Result<Option<u32>, ImageError> {
+    match names.iter().filter_map(|member| object.get(member)).next() {
+        Some(&Json::Number(n)) if n >= 0.0 && n <= u32::max_value() as f64 && n.fract() == 0.0 => Ok(Some(n as u32)),
+        Some(&Json::Number(n)) => Err(invalid(&format!("`{}` of region `{}` is not a whole number of pixels: {}", names[0], name, n))),
+        _ => Ok(None),
+    }
+}
+
+fn invalid(reason: &str) -> ImageError {
+    ImageError::Decode(format!("invalid atlas description: {}", reason))
+}
+
+/// The subset of JSON values the atlas descriptions are made of.
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref members) => members.iter().find(|&&(ref name, _)| name == key).map(|&(_, ref value)| value),
+            _ => None,
+        }
+    }
+}
+
+/// How deep the arrays and objects may nest, far deeper than any atlas description, before the recursion gets near the
+/// end of the stack.
+const MAX_DEPTH: usize = 128;
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    at: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(json: &'a str) -> Parser<'a> {
+        Parser { bytes: json.as_bytes(), at: 0, depth: 0 }
+    }
+    fn parse(&mut self) -> Result<Json, ImageError> {
+        let value = self.value()?;
+        self.skip_whitespace();
+        if self.at < self.bytes.len() {
+            return Err(self.error("trailing characters"));
+        }
+        Ok(value)
+    }
+    fn value(&mut self) -> Result<Json, ImageError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.nested(Parser::object),
+            Some(b'[') => self.nested(Parser::array),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b'-') | Some(b'0'...b'9') => self.number(),
+            _ => Err(self.error("value expected")),
+        }
+    }
+    fn nested(&mut self, parse: fn(&mut Parser<'a>) -> Result<Json, ImageError>) -> Result<Json, ImageError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("nested too deep"));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+    fn object(&mut self) -> Result<Json, ImageError> {
+        let mut members = Vec::new();
+        self.at += 1;
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.at += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("member name expected"));
+            }
+            let name = self.string()?;
+            self.expect(b':')?;
+            members.push((name, self.value()?));
+            self.skip_whitespace();
+            match self.next() {
+                Some(b',') => continue,
+                Some(b'}') => return Ok(Json::Object(members)),
+                _ => return Err(self.error("`,` or `}` expected")),
+            }
+        }
+    }
+    fn array(&mut self) -> Result<Json, ImageError> {
+        let mut items = Vec::new();
+        self.at += 1;
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.at += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(b',') => continue,
+                Some(b']') => return Ok(Json::Array(items)),
+                _ => return Err(self.error("`,` or `]` expected")),
+            }
+        }
+    }
+    fn string(&mut self) -> Result<String, ImageError> {
+        let mut bytes = Vec::new();
+        self.at += 1;
+        loop {
+            match self.next() {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    let escaped = match self.next() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let mut code = self.hex4()?;
+                            // surrogate pair
+                            if code >= 0xD800 && code < 0xDC00 && self.bytes[self.at..].starts_with(b"\\u") {
+                                self.at += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+                            }
+                            char::from_u32(code).unwrap_or('\u{FFFD}')
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(escaped.encode_utf8(&mut buf).as_bytes());
+                }
+                Some(b) => bytes.push(b),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8"))
+    }
+    fn hex4(&mut self) -> Result<u32, ImageError> {
+        let digits = self.bytes.get(self.at..self.at + 4).and_then(|d| str::from_utf8(d).ok()).and_then(|d| u32::from_str_radix(d, 16).ok());
+        self.at += 4;
+        digits.ok_or_else(|| self.error("invalid unicode escape"))
+    }
+    fn number(&mut self) -> Result<Json, ImageError> {
+        let start = self.at;
+        while let Some(b'-') | Some(b'+') | Some(b'.') | Some(b'e') | Some(b'E') | Some(b'0'...b'9') = self.peek() {
+            self.at += 1;
+        }
+        str::from_utf8(&self.bytes[start..self.at]).ok().and_then(|n| n.parse().ok()).map(Json::Number).ok_or_else(|| self.error("invalid number"))
+    }
+    fn literal(&mut self, literal: &str, value: Json) -> Result<Json, ImageError> {
+        if self.bytes[self.at..].starts_with(literal.as_bytes()) {
+            self.at += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("value expected"))
+        }
+    }
+    fn expect(&mut self, byte: u8) -> Result<(), ImageError> {
+        self.skip_whitespace();
+        if self.next() == Some(byte) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("`{}` expected", byte as char)))
+        }
+    }
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.at += 1;
+        }
+    }
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.at).cloned()
+    }
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek();
+        self.at += 1;
+        byte
+    }
+    fn error(&self, reason: &str) -> ImageError {
+        invalid(&format!("{} at byte {}", reason, self.at))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::RgbaImage;
+
+    fn parse(json: &str) -> Result<Json, ImageError> {
+        Parser::new(json).parse()
+    }
+    fn is_invalid(result: Result<Json, ImageError>) -> bool {
+        match result {
+            Err(ImageError::Decode(_)) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn nested() {
+        assert_eq!(parse("[[[1]]]").unwrap(), Json::Array(vec![Json::Array(vec![Json::Array(vec![Json::Number(1.0)])])]));
+        let deep = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert!(parse(&deep).is_ok());
+        let deeper = format!("{}{}", "[".repeat(MAX_DEPTH + 1), "]".repeat(MAX_DEPTH + 1));
+        assert!(is_invalid(parse(&deeper)));
+        // would overflow the stack without the limit
+        assert!(is_invalid(parse(&"{\"a\":[".repeat(100_000))));
+    }
+
+    #[test]
+    fn escapes() {
+        let parsed = parse(r#""\"\\\/\b\f\n\r\t é 😀 é""#).unwrap();
+        assert_eq!(parsed, Json::String("\"\\/\u{8}\u{c}\n\r\t é 😀 é".into()));
+        assert!(is_invalid(parse(r#""\x""#)));
+        assert!(is_invalid(parse(r#""\u12""#)));
+    }
+
+    #[test]
+    fn malformed() {
+        for json in &["", "{", "{\"a\" 1}", "{\"a\":1,}", "[1 2]", "[1,]", "tru", "nul", "\"open", "-", "1 2", "{1:2}", "{\"a\":1}}"] {
+            assert!(is_invalid(parse(json)), "{}", json);
+        }
+    }
+
+    #[test]
+    fn trimmed_frame() {
+        let json = r#"{ "frames": { "play.png": {
+            "frame": { "x": 4, "y": 2, "w": 10, "h": 12 }, "rotated": false, "trimmed": true,
+            "spriteSourceSize": { "x": 3, "y": 1, "w": 10, "h": 12 }, "sourceSize": { "w": 16, "h": 16 } } } }"#;
+        let atlas = Atlas::from_json(ImageSource::new(RgbaImage::new(32, 32)), json).unwrap();
+        assert_eq!(atlas.region("play.png"), Some(Region::new(4, 2, 10, 12).with_trim(Trim { x: 3, y: 1, width: 16, height: 16 })));
+
+        let json = r#"{ "frames": [ { "filename": "play.png", "frame": { "x": 4, "y": 2, "w": 10, "h": 12 }, "trimmed": true } ] }"#;
+        assert!(Atlas::from_json(ImageSource::new(RgbaImage::new(32, 32)), json).is_err());
+    }
+
+    #[test]
+    fn whole_pixels() {
+        let atlas = Atlas::from_json(ImageSource::new(RgbaImage::new(32, 32)), r#"{ "a": { "x": 10.0, "y": 2, "w": 4, "h": 4 } }"#).unwrap();
+        assert_eq!(atlas.region("a"), Some(Region::new(10, 2, 4, 4)));
+
+        for rect in &[r#"{ "x": 10.5, "y": 2, "w": 4, "h": 4 }"#, r#"{ "x": 1e12, "y": 2, "w": 4, "h": 4 }"#, r#"{ "x": -1, "y": 2, "w": 4, "h": 4 }"#] {
+            let result = Atlas::from_json(ImageSource::new(RgbaImage::new(32, 32)), &format!(r#"{{ "a": {} }}"#, rect));
+            assert!(match result {
+                Err(ImageError::Decode(_)) => true,
+                _ => false,
+            }, "{}", rect);
+            let json = format!(r#"{{ "frames": {{ "a": {{ "frame": {{ "x": 0, "y": 0, "w": 4, "h": 4 }}, "trimmed": true, "spriteSourceSize": {}, "sourceSize": {{ "w": 8, "h": 8 }} }} }} }}"#, rect);
+            assert!(Atlas::from_json(ImageSource::new(RgbaImage::new(32, 32)), &json).is_err(), "{}", rect);
+        }
+    }
+}
@@ -0,0 +1,320 @@
+//! Process-wide cache of decoded content, shared by all the images showing the same picture.
+//!
+//! Entries are reference-counted: an entry still shown by an image is never evicted, the unused ones are dropped
+//! least recently used first once the cached bytes exceed the budget.
+
+use super::{load, ImageError, ImageSource};
+
+use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Budget used until `set_budget` is called.
+pub const DEFAULT_BUDGET: usize = 64 * 1024 * 1024;
+
+lazy_static! {
+    static ref CACHE: Mutex<Cache> = Mutex::new(Cache::new(DEFAULT_BUDGET));
+}
+
+/// Snapshot of the cache counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Decoded pixel bytes held by the cache, including the entries that cannot be evicted because they are in use.
+    pub bytes: usize,
+    pub entries: usize,
+    pub budget: usize,
+}
+
+/// Sets the amount of decoded pixel bytes to keep, evicting the unused entries over it right away.
+pub fn set_budget(bytes: usize) {
+    let mut cache = CACHE.lock().unwrap();
+    cache.budget = bytes;
+    cache.evict();
+}
+
+pub fn stats() -> CacheStats {
+    CACHE.lock().unwrap().stats()
+}
+
+/// Forgets all the entries and resets the counters. Content still shown stays alive until its images drop it.
+pub fn clear() {
+    let mut cache = CACHE.lock().unwrap();
+    let budget = cache.budget;
+    *cache = Cache::new(budget);
+}
+
+/// Decoded content of the file at `path`, decoding it only if it is not cached or the file or any of its resolution
+/// variants has been modified, added or removed since.
+pub fn from_path(path: &Path) -> Result<ImageSource, ImageError> {
+    let mut modified = vec![path.metadata()?.modified().ok()];
+    let path = path.canonicalize()?;
+    modified.extend(load::variant_paths(&path).iter().map(|&(ref path, _)| path.metadata().and_then(|metadata| metadata.modified()).ok()));
+    get_or_insert(Key::Path(path.clone(), modified), || load::from_path(&path))
+}
+
+/// Decoded content of an in-memory encoded image, looked up by the hash of the encoded data.
+pub fn from_bytes(bytes: &[u8]) -> Result<ImageSource, ImageError> {
+    get_or_insert(Key::Bytes(hash(bytes), bytes.len(), sample(bytes)), || load::from_bytes(bytes))
+}
+
+fn get_or_insert<F: FnOnce() -> Result<ImageSource, ImageError>>(key: Key, decode: F) -> Result<ImageSource, ImageError> {
+    if let Some(pixels) = CACHE.lock().unwrap().get(&key) {
+        return Ok(pixels);
+    }
+    // decoded with the cache unlocked, so the other threads are not blocked meanwhile
+    let pixels = decode()?;
+    Ok(CACHE.lock().unwrap().insert(key, pixels))
+}
+
+fn hash(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Bytes picked evenly across `data`, compared on top of the hash and the length, so data colliding in the hash
+/// alone does not get the content of another picture.
+fn sample(data: &[u8]) -> Vec<u8> {
+    let count = cmp::min(SAMPLE_SIZE, data.len());
+    (0..count).map(|i| data[i * data.len() / count]).collect()
+}
+
+const SAMPLE_SIZE: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    /// Canonical path, and when the file and each of its resolution variants were modified, `None` for those missing.
+    Path(PathBuf, Vec<Option<SystemTime>>),
+    /// Hash, length and `sample` of encoded data.
+    Bytes(u64, usize, Vec<u8>),
+}
+
+struct Entry {
+    pixels: ImageSource,
+    last_used: u64,
+}
+
+struct Cache {
+    entries: HashMap<Key, Entry>,
+    budget: usize,
+    bytes: usize,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl Cache {
+    fn new(budget: usize) -> Cache {
+        Cache {
+            entries: HashMap::new(),
+            budget: budget,
+            bytes: 0,
+            clock: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            bytes: self.bytes,
+            entries: self.entries.len(),
+            budget: self.budget,
+        }
+    }
+    fn get(&mut self, key: &Key) -> Option<ImageSource> {
+        self.clock += 1;
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                self.hits += 1;
+                entry.last_used = self.clock;
+                Some(entry.pixels.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+    /// Stores `pixels`, unless another thread has stored the same content meanwhile, returning the stored one.
+    fn insert(&mut self, key: Key, pixels: ImageSource) -> ImageSource {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.last_used = self.clock;
+            return entry.pixels.clone();
+        }
+        self.bytes += pixels.byte_size();
+        self.entries.insert(
+            key,
+            Entry {
+                pixels: pixels.clone(),
+                last_used: self.clock,
+            },
+        );
+        self.evict();
+        pixels
+    }
+    fn evict(&mut self) {
+        if self.bytes <= self.budget {
+            return;
+        }
+        let mut unused = self
+            .entries
+            .iter()
+            .filter(|&(_, entry)| entry.pixels.ref_count() < 2)
+            .map(|(key, entry)| (entry.last_used, key.clone()))
+            .collect::<Vec<_>>();
+        unused.sort_by_key(|&(last_used, _)| last_used);
+        for (_, key) in unused {
+            if self.bytes <= self.budget {
+                break;
+            }
+            if let Some(entry) = self.entries.remove(&key) {
+                self.bytes -= entry.pixels.byte_size();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::RgbaImage;
+
+    use std::{env, fs, process};
+
+    fn key(name: &str) -> Key {
+        Key::Path(PathBuf::from(name), Vec::new())
+    }
+    /// Content of `bytes` decoded bytes.
+    fn content(bytes: u32) -> ImageSource {
+        ImageSource::new(RgbaImage::new(bytes / 4, 1))
+    }
+
+    #[test]
+    fn stats() {
+        let mut cache = Cache::new(1000);
+        assert!(cache.get(&key("a")).is_none());
+        cache.insert(key("a"), content(100));
+        assert!(cache.get(&key("a")).is_some());
+        assert!(cache.get(&key("a")).is_some());
+        assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 1, bytes: 100, entries: 1, budget: 1000 });
+    }
+
+    #[test]
+    fn insert_twice() {
+        let mut cache = Cache::new(1000);
+        let first = cache.insert(key("a"), content(100));
+        // another thread decoded the same content meanwhile
+        let second = cache.insert(key("a"), content(100));
+        assert!(first.ptr_eq(&second));
+        assert_eq!(cache.stats().bytes, 100);
+    }
+
+    #[test]
+    fn least_recently_used_first() {
+        let mut cache = Cache::new(300);
+        for name in &["a", "b", "c"] {
+            cache.insert(key(name), content(100));
+        }
+        cache.get(&key("a"));
+        cache.insert(key("d"), content(100));
+        assert!(cache.entries.contains_key(&key("a")));
+        assert!(!cache.entries.contains_key(&key("b")));
+        assert!(cache.entries.contains_key(&key("c")));
+        assert!(cache.entries.contains_key(&key("d")));
+        assert_eq!(cache.stats().bytes, 300);
+    }
+
+    #[test]
+    fn budget() {
+        let mut cache = Cache::new(1000);
+        for name in &["a", "b", "c", "d"] {
+            cache.insert(key(name), content(100));
+        }
+        cache.budget = 150;
+        cache.evict();
+        assert_eq!(cache.stats().entries, 1);
+        assert_eq!(cache.stats().bytes, 100);
+        assert!(cache.entries.contains_key(&key("d")));
+
+        // content over the budget on its own is kept only while it is in use
+        cache.insert(key("e"), content(200));
+        assert_eq!(cache.stats().entries, 1);
+        assert_eq!(cache.stats().bytes, 200);
+        cache.evict();
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(cache.stats().bytes, 0);
+    }
+
+    #[test]
+    fn in_use_survives() {
+        let mut cache = Cache::new(150);
+        let shown = cache.insert(key("a"), content(100));
+        cache.insert(key("b"), content(100));
+        cache.insert(key("c"), content(100));
+        // "a" is the least recently used one, but an image shows it still
+        assert!(cache.entries.contains_key(&key("a")));
+        assert!(cache.entries.contains_key(&key("c")));
+        assert_eq!(cache.stats().bytes, 200);
+
+        drop(shown);
+        cache.insert(key("d"), content(20));
+        assert!(!cache.entries.contains_key(&key("a")));
+        assert_eq!(cache.stats().bytes, 120);
+    }
+
+    #[test]
+    fn variants_evicted() {
+        let mut cache = Cache::new(1000);
+        let shown = cache.insert(key("a"), ImageSource::with_variants(vec![content(100), content(400).with_density(2.0)]));
+        assert_eq!(cache.stats().bytes, 500);
+        assert_eq!(shown.ref_count(), 2);
+
+        drop(shown);
+        cache.budget = 100;
+        cache.evict();
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(cache.stats().bytes, 0);
+    }
+
+    #[test]
+    fn variant_added() {
+        let dir = env::temp_dir().join(format!("plygui-image-cache-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (path, variant) = (dir.join("a.png"), dir.join("a@2x.png"));
+        RgbaImage::new(1, 1).save(&path).unwrap();
+        let first = from_path(&path).unwrap();
+        assert!(first.ptr_eq(&from_path(&path).unwrap()));
+
+        // the variant put next to the file afterwards is picked up, the file itself being unchanged
+        RgbaImage::new(2, 2).save(&variant).unwrap();
+        let second = from_path(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(!first.ptr_eq(&second));
+        assert_eq!(second.variant(2.0).dimensions(), (2, 2));
+    }
+
+    #[test]
+    fn bytes_keys() {
+        let data = (0..1000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let mut changed = data.clone();
+        changed[500] = 0;
+        let bytes = |data: &[u8]| Key::Bytes(hash(data), data.len(), sample(data));
+        assert_eq!(bytes(&data), bytes(&data.clone()));
+        // a colliding hash alone is not enough
+        assert_ne!(Key::Bytes(1, data.len(), sample(&data)), Key::Bytes(1, changed.len(), sample(&changed)));
+        assert_ne!(Key::Bytes(1, data.len(), sample(&data)), Key::Bytes(1, 999, sample(&data[..999])));
+        assert_eq!(sample(&data).len(), SAMPLE_SIZE);
+        assert_eq!(sample(&data[..10]), &data[..10]);
+        assert!(sample(&[]).is_empty());
+    }
+}
@@ -0,0 +1,387 @@
+use super::{Gravity, ScalePolicy, SizeConstraints};
+
+use plygui_api::layout;
+
+use std::cmp;
+
+/// Integer rectangle in pixels, `x`/`y` being the top-left corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Rect {
+        Rect { x, y, width, height }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.width < 1 || self.height < 1
+    }
+    /// The part lying within both rectangles, empty if there is none.
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        let (x, y) = (cmp::max(self.x, other.x), cmp::max(self.y, other.y));
+        let right = cmp::min(self.x + self.width, other.x + other.width);
+        let bottom = cmp::min(self.y + self.height, other.y + other.height);
+        Rect::new(x, y, cmp::max(0, right - x), cmp::max(0, bottom - y))
+    }
+}
+
+/// Which part of the content goes to which part of the drawing area.
+///
+/// `src` is in content pixels, `dst` is relative to the top-left corner of the area (margins excluded).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    pub src: Rect,
+    pub dst: Rect,
+}
+
+impl Placement {
+    pub fn scale_x(&self) -> f64 {
+        self.dst.width as f64 / self.src.width as f64
+    }
+    pub fn scale_y(&self) -> f64 {
+        self.dst.height as f64 / self.src.height as f64
+    }
+    pub fn is_empty(&self) -> bool {
+        self.src.is_empty() || self.dst.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Align {
+    Start,
+    Center,
+    End,
+}
+
+impl Gravity {
+    fn align(self) -> (Align, Align) {
+        match self {
+            Gravity::TopLeft => (Align::Start, Align::Start),
+            Gravity::Top => (Align::Center, Align::Start),
+            Gravity::TopRight => (Align::End, Align::Start),
+            Gravity::Left => (Align::Start, Align::Center),
+            Gravity::Center => (Align::Center, Align::Center),
+            Gravity::Right => (Align::End, Align::Center),
+            Gravity::BottomLeft => (Align::Start, Align::End),
+            Gravity::Bottom => (Align::Center, Align::End),
+            Gravity::BottomRight => (Align::End, Align::End),
+        }
+    }
+}
+
+/// Maps `content` of size (width, height) into `area` of size (width, height) according to `policy`.
+/// The `*Center`, `FitInteger` and `None` policies are aligned by `gravity`, the `*Start` and `*End` ones keep their own alignment.
+/// `focal_point` is the normalized point of the content `ScalePolicy::CropFocus` and `ScalePolicy::CropAuto` keep the crop window around.
+pub fn place(policy: ScalePolicy, gravity: Gravity, focal_point: (f32, f32), content: (u32, u32), area: (i32, i32)) -> Placement {
+    let (cw, ch) = (content.0 as i32, content.1 as i32);
+    let (aw, ah) = (area.0.max(0), area.1.max(0));
+    let whole = Rect::new(0, 0, cw, ch);
+
+    if cw < 1 || ch < 1 || aw < 1 || ah < 1 {
+        return Placement { src: whole, dst: Rect::default() };
+    }
+
+    let start = (Align::Start, Align::Start);
+    let end = (Align::End, Align::End);
+    let gravity = gravity.align();
+    match policy {
+        ScalePolicy::FitStart => fit(content, area, start),
+        ScalePolicy::FitCenter => fit(content, area, gravity),
+        ScalePolicy::FitEnd => fit(content, area, end),
+        ScalePolicy::FitInteger => {
+            let factor = integer_factor(content, area) as i32;
+            if factor > 1 {
+                let (w, h) = (cw * factor, ch * factor);
+                Placement {
+                    src: whole,
+                    dst: Rect::new(offset(aw - w, gravity.0), offset(ah - h, gravity.1), w, h),
+                }
+            } else {
+                none(content, area, gravity)
+            }
+        }
+        ScalePolicy::CropStart => crop(content, area, start),
+        ScalePolicy::CropCenter => crop(content, area, gravity),
+        ScalePolicy::CropEnd => crop(content, area, end),
+        ScalePolicy::CropFocus | ScalePolicy::CropAuto => {
+            let mut placement = crop(content, area, start);
+            placement.src.x = focus(focal_point.0, cw, placement.src.width);
+            placement.src.y = focus(focal_point.1, ch, placement.src.height);
+            placement
+        }
+        ScalePolicy::Stretch => Placement { src: whole, dst: Rect::new(0, 0, aw, ah) },
+        // The tiled frame is composed by `render::tile`, here is the single unscaled tile for the backends that cannot do it.
+        ScalePolicy::Tile { .. } => {
+            let (w, h) = (cw.min(aw), ch.min(ah));
+            Placement {
+                src: Rect::new(0, 0, w, h),
+                dst: Rect::new(0, 0, w, h),
+            }
+        }
+        ScalePolicy::None => none(content, area, gravity),
+    }
+}
+
+/// Control size for the `layout` sizes offered `parent` size, content area being the control size minus `margins` (horizontal, vertical).
+pub fn measure(natural: (u32, u32), layout: (&layout::Size, &layout::Size), parent: (u16, u16), margins: (i32, i32), constraints: &SizeConstraints) -> (u16, u16) {
+    let known = |size: &layout::Size, parent: u16, margin: i32| match *size {
+        layout::Size::MatchParent => Some(parent as i32 - margin),
+        layout::Size::Exact(size) => Some(size as i32 - margin),
+        layout::Size::WrapContent => None,
+    };
+    let area = (known(layout.0, parent.0, margins.0), known(layout.1, parent.1, margins.1));
+    let (mut w, mut h) = wrap_content(natural, area);
+
+    let limit = |size: i32, max: Option<u16>, margin: i32| max.map_or(size, |max| cmp::min(size, max as i32 - margin));
+    w = limit(w, constraints.max_width, margins.0);
+    h = limit(h, constraints.max_height, margins.1);
+    if constraints.no_upscale {
+        w = cmp::min(w, natural.0 as i32);
+        h = cmp::min(h, natural.1 as i32);
+    }
+    if constraints.lock_aspect_ratio && natural.0 > 0 && natural.1 > 0 && w > 0 && h > 0 {
+        let dst = fit(natural, (w, h), (Align::Start, Align::Start)).dst;
+        w = dst.width;
+        h = dst.height;
+    }
+    let extend = |size: i32, min: Option<u16>, margin: i32| min.map_or(size, |min| cmp::max(size, min as i32 - margin));
+    w = extend(w, constraints.min_width, margins.0);
+    h = extend(h, constraints.min_height, margins.1);
+
+    (cmp::max(0, w + margins.0) as u16, cmp::max(0, h + margins.1) as u16)
+}
+
+/// Area size for showing `content`, given the known `area` sizes along the axes that are not wrapped (`None`).
+/// A wrapped axis is derived from the known one keeping the aspect ratio, or is the natural content size if both are wrapped.
+pub fn wrap_content(content: (u32, u32), area: (Option<i32>, Option<i32>)) -> (i32, i32) {
+    let (cw, ch) = (content.0 as i32, content.1 as i32);
+    match area {
+        (Some(w), Some(h)) => (w, h),
+        (Some(w), None) if cw > 0 => (w, (w.max(0) as i64 * ch as i64 / cw as i64) as i32),
+        (None, Some(h)) if ch > 0 => ((h.max(0) as i64 * cw as i64 / ch as i64) as i32, h),
+        (w, h) => (w.unwrap_or(cw), h.unwrap_or(ch)),
+    }
+}
+
+/// Largest whole-number factor `content` fits into `area` with, at least 1.
+pub fn integer_factor(content: (u32, u32), area: (i32, i32)) -> u32 {
+    if content.0 < 1 || content.1 < 1 || area.0 < 1 || area.1 < 1 {
+        return 1;
+    }
+    cmp::max(1, cmp::min(area.0 as u32 / content.0, area.1 as u32 / content.1))
+}
+
+/// Size of the content part (in content pixels) the `Crop*` policies show in `area`.
+pub fn crop_size(content: (u32, u32), area: (i32, i32)) -> (u32, u32) {
+    if content.0 < 1 || content.1 < 1 || area.0 < 1 || area.1 < 1 {
+        return (0, 0);
+    }
+    let src = crop(content, area, (Align::Start, Align::Start)).src;
+    (src.width as u32, src.height as u32)
+}
+
+/// The logical pixels covering the `rect` of device pixels, for the toolkits repainting in logical pixels.
+pub fn logical_rect(rect: Rect, scale_factor: f32) -> Rect {
+    let scale = scale_factor as f64;
+    let (left, top) = ((rect.x as f64 / scale).floor() as i32, (rect.y as f64 / scale).floor() as i32);
+    let (right, bottom) = (((rect.x + rect.width) as f64 / scale).ceil() as i32, ((rect.y + rect.height) as f64 / scale).ceil() as i32);
+    Rect::new(left, top, right - left, bottom - top)
+}
+
+fn fit(content: (u32, u32), area: (i32, i32), align: (Align, Align)) -> Placement {
+    let (cw, ch) = (content.0 as f64, content.1 as f64);
+    let rate = (area.0 as f64 / cw).min(area.1 as f64 / ch);
+    let w = ((cw * rate).round() as i32).max(1).min(area.0);
+    let h = ((ch * rate).round() as i32).max(1).min(area.1);
+    Placement {
+        src: Rect::new(0, 0, content.0 as i32, content.1 as i32),
+        dst: Rect::new(offset(area.0 - w, align.0), offset(area.1 - h, align.1), w, h),
+    }
+}
+
+fn crop(content: (u32, u32), area: (i32, i32), align: (Align, Align)) -> Placement {
+    let (cw, ch) = (content.0 as f64, content.1 as f64);
+    let rate = (area.0 as f64 / cw).max(area.1 as f64 / ch);
+    let w = ((area.0 as f64 / rate).round() as i32).max(1).min(content.0 as i32);
+    let h = ((area.1 as f64 / rate).round() as i32).max(1).min(content.1 as i32);
+    Placement {
+        src: Rect::new(offset(content.0 as i32 - w, align.0), offset(content.1 as i32 - h, align.1), w, h),
+        dst: Rect::new(0, 0, area.0, area.1),
+    }
+}
+
+fn none(content: (u32, u32), area: (i32, i32), align: (Align, Align)) -> Placement {
+    let (sx, dx, w) = unscaled(content.0 as i32, area.0, align.0);
+    let (sy, dy, h) = unscaled(content.1 as i32, area.1, align.1);
+    Placement {
+        src: Rect::new(sx, sy, w, h),
+        dst: Rect::new(dx, dy, w, h),
+    }
+}
+
+/// 1:1 mapping along one axis: returns the source offset, the destination offset and the visible length.
+fn unscaled(content: i32, area: i32, align: Align) -> (i32, i32, i32) {
+    if content > area {
+        (offset(content - area, align), 0, area)
+    } else {
+        (0, offset(area - content, align), content)
+    }
+}
+
+/// Start of a `visible` long window centered at `point` (normalized) as close as the `content` length allows.
+fn focus(point: f32, content: i32, visible: i32) -> i32 {
+    let center = (point.max(0.0).min(1.0) as f64 * content as f64).round() as i32;
+    (center - visible / 2).max(0).min(content - visible)
+}
+
+fn offset(free: i32, align: Align) -> i32 {
+    match align {
+        Align::Start => 0,
+        Align::Center => free / 2,
+        Align::End => free,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::TileMode;
+    use super::*;
+
+    const CONTENT: (u32, u32) = (100, 50);
+
+    fn dst(policy: ScalePolicy, gravity: Gravity) -> Rect {
+        place(policy, gravity, (0.5, 0.5), CONTENT, (200, 200)).dst
+    }
+    fn src(policy: ScalePolicy, focal_point: (f32, f32)) -> Rect {
+        place(policy, Gravity::Center, focal_point, CONTENT, (200, 200)).src
+    }
+
+    #[test]
+    fn fit() {
+        assert_eq!(dst(ScalePolicy::FitStart, Gravity::BottomRight), Rect::new(0, 0, 200, 100));
+        assert_eq!(dst(ScalePolicy::FitCenter, Gravity::Center), Rect::new(0, 50, 200, 100));
+        assert_eq!(dst(ScalePolicy::FitCenter, Gravity::Top), Rect::new(0, 0, 200, 100));
+        assert_eq!(dst(ScalePolicy::FitCenter, Gravity::BottomRight), Rect::new(0, 100, 200, 100));
+        assert_eq!(dst(ScalePolicy::FitEnd, Gravity::TopLeft), Rect::new(0, 100, 200, 100));
+        assert_eq!(src(ScalePolicy::FitCenter, (0.5, 0.5)), Rect::new(0, 0, 100, 50));
+    }
+
+    #[test]
+    fn crop() {
+        assert_eq!(src(ScalePolicy::CropStart, (0.5, 0.5)), Rect::new(0, 0, 50, 50));
+        assert_eq!(src(ScalePolicy::CropCenter, (0.5, 0.5)), Rect::new(25, 0, 50, 50));
+        assert_eq!(src(ScalePolicy::CropEnd, (0.5, 0.5)), Rect::new(50, 0, 50, 50));
+        assert_eq!(dst(ScalePolicy::CropCenter, Gravity::Center), Rect::new(0, 0, 200, 200));
+        // gravity picks the visible part of the centered crops
+        assert_eq!(place(ScalePolicy::CropCenter, Gravity::Right, (0.5, 0.5), CONTENT, (200, 200)).src, Rect::new(50, 0, 50, 50));
+    }
+
+    #[test]
+    fn crop_focus() {
+        assert_eq!(src(ScalePolicy::CropFocus, (0.3, 0.5)), Rect::new(5, 0, 50, 50));
+        // the window stops at the content edges
+        assert_eq!(src(ScalePolicy::CropFocus, (0.9, 0.5)), Rect::new(50, 0, 50, 50));
+        assert_eq!(src(ScalePolicy::CropFocus, (-1.0, 2.0)), Rect::new(0, 0, 50, 50));
+        assert_eq!(src(ScalePolicy::CropAuto, (0.3, 0.5)), Rect::new(5, 0, 50, 50));
+    }
+
+    #[test]
+    fn stretch_tile_none() {
+        let stretch = place(ScalePolicy::Stretch, Gravity::BottomRight, (0.5, 0.5), CONTENT, (200, 200));
+        assert_eq!((stretch.src, stretch.dst), (Rect::new(0, 0, 100, 50), Rect::new(0, 0, 200, 200)));
+
+        let tile = ScalePolicy::Tile { mode: TileMode::Repeat, offset: (0, 0) };
+        let tile = place(tile, Gravity::Center, (0.5, 0.5), CONTENT, (60, 40));
+        assert_eq!((tile.src, tile.dst), (Rect::new(0, 0, 60, 40), Rect::new(0, 0, 60, 40)));
+
+        assert_eq!(dst(ScalePolicy::None, Gravity::Center), Rect::new(50, 75, 100, 50));
+        assert_eq!(dst(ScalePolicy::None, Gravity::BottomLeft), Rect::new(0, 150, 100, 50));
+        // larger than the area, the gravity picks the visible part
+        let none = place(ScalePolicy::None, Gravity::Center, (0.5, 0.5), CONTENT, (60, 40));
+        assert_eq!((none.src, none.dst), (Rect::new(20, 5, 60, 40), Rect::new(0, 0, 60, 40)));
+        let none = place(ScalePolicy::None, Gravity::TopLeft, (0.5, 0.5), CONTENT, (60, 40));
+        assert_eq!(none.src, Rect::new(0, 0, 60, 40));
+    }
+
+    #[test]
+    fn fit_integer() {
+        let place_integer = |gravity, area| place(ScalePolicy::FitInteger, gravity, (0.5, 0.5), (30, 20), area).dst;
+        assert_eq!(place_integer(Gravity::Center, (100, 70)), Rect::new(5, 5, 90, 60));
+        assert_eq!(place_integer(Gravity::TopLeft, (100, 70)), Rect::new(0, 0, 90, 60));
+        assert_eq!(place_integer(Gravity::BottomRight, (100, 70)), Rect::new(10, 10, 90, 60));
+        // no whole factor above 1 fits, shown as it is
+        assert_eq!(place_integer(Gravity::Center, (50, 70)), Rect::new(10, 25, 30, 20));
+        assert_eq!(integer_factor((30, 20), (100, 70)), 3);
+        assert_eq!(integer_factor((30, 20), (10, 10)), 1);
+        assert_eq!(integer_factor((0, 20), (100, 70)), 1);
+    }
+
+    #[test]
+    fn empty() {
+        assert!(place(ScalePolicy::FitCenter, Gravity::Center, (0.5, 0.5), (0, 50), (200, 200)).is_empty());
+        assert!(place(ScalePolicy::CropCenter, Gravity::Center, (0.5, 0.5), CONTENT, (0, 200)).is_empty());
+        assert_eq!(crop_size(CONTENT, (-5, 200)), (0, 0));
+        assert_eq!(crop_size(CONTENT, (200, 200)), (50, 50));
+    }
+
+    #[test]
+    fn logical() {
+        assert_eq!(logical_rect(Rect::new(3, 4, 5, 6), 1.0), Rect::new(3, 4, 5, 6));
+        assert_eq!(logical_rect(Rect::new(3, 4, 5, 6), 2.0), Rect::new(1, 2, 3, 3));
+        assert_eq!(logical_rect(Rect::new(0, 0, 1, 1), 1.5), Rect::new(0, 0, 1, 1));
+    }
+
+    #[test]
+    fn wrap() {
+        assert_eq!(wrap_content(CONTENT, (None, None)), (100, 50));
+        assert_eq!(wrap_content(CONTENT, (Some(200), None)), (200, 100));
+        assert_eq!(wrap_content(CONTENT, (None, Some(100))), (200, 100));
+        assert_eq!(wrap_content(CONTENT, (Some(10), Some(20))), (10, 20));
+        assert_eq!(wrap_content(CONTENT, (Some(-10), None)), (-10, 0));
+        assert_eq!(wrap_content((0, 50), (Some(200), None)), (200, 50));
+    }
+
+    #[test]
+    fn measured() {
+        let wrap = layout::Size::WrapContent;
+        let measure = |width: &layout::Size, height: &layout::Size, margins, constraints| measure(CONTENT, (width, height), (300, 300), margins, &constraints);
+        let none = SizeConstraints::default();
+
+        assert_eq!(measure(&wrap, &wrap, (0, 0), none), (100, 50));
+        assert_eq!(measure(&layout::Size::Exact(200), &wrap, (0, 0), none), (200, 100));
+        assert_eq!(measure(&layout::Size::MatchParent, &layout::Size::MatchParent, (0, 0), none), (300, 300));
+        // the margins are not part of the content area
+        assert_eq!(measure(&layout::Size::Exact(210), &wrap, (10, 10), none), (210, 110));
+    }
+
+    #[test]
+    fn constrained() {
+        let wrap = layout::Size::WrapContent;
+        let parent = layout::Size::MatchParent;
+        let measure = |width: &layout::Size, height: &layout::Size, margins, constraints| measure(CONTENT, (width, height), (300, 300), margins, &constraints);
+
+        let max = SizeConstraints { max_width: Some(80), ..Default::default() };
+        assert_eq!(measure(&wrap, &wrap, (0, 0), max), (80, 50));
+        let locked = SizeConstraints { lock_aspect_ratio: true, ..max };
+        assert_eq!(measure(&wrap, &wrap, (0, 0), locked), (80, 40));
+        assert_eq!(measure(&parent, &parent, (0, 0), SizeConstraints { lock_aspect_ratio: true, ..Default::default() }), (300, 150));
+
+        let min = SizeConstraints { min_width: Some(150), min_height: Some(60), ..Default::default() };
+        assert_eq!(measure(&wrap, &wrap, (0, 0), min), (150, 60));
+        // the minimum wins over the maximum
+        let both = SizeConstraints { min_width: Some(120), ..max };
+        assert_eq!(measure(&wrap, &wrap, (0, 0), both), (120, 50));
+
+        let no_upscale = SizeConstraints { no_upscale: true, ..Default::default() };
+        assert_eq!(measure(&parent, &parent, (0, 0), no_upscale), (100, 50));
+        assert_eq!(measure(&layout::Size::Exact(40), &wrap, (0, 0), no_upscale), (40, 20));
+
+        // the limits are on the control size, margins included
+        let max = SizeConstraints { max_width: Some(60), ..Default::default() };
+        assert_eq!(measure(&wrap, &wrap, (10, 10), max), (60, 60));
+    }
+}
@@ -3,7 +3,30 @@ extern crate lazy_static;
 #[macro_use]
 extern crate plygui_api;
 
+extern crate gif;
 extern crate image;
+#[cfg(feature = "svg")]
+extern crate flate2;
+#[cfg(feature = "svg")]
+extern crate resvg;
+#[cfg(feature = "svg")]
+extern crate tiny_skia;
+#[cfg(feature = "svg")]
+extern crate usvg;
+
+mod animation;
+pub mod atlas;
+pub mod cache;
+mod geometry;
+mod load;
+mod pixels;
+mod render;
+mod resample;
+mod saliency;
+pub mod sink;
+mod transform;
+#[cfg(feature = "svg")]
+mod vector;
 
 #[cfg(all(target_os = "windows", feature = "win32"))]
 mod lib_win32;
@@ -45,6 +68,8 @@ extern crate plygui_gtk;
 #[cfg(feature = "gtk3")]
 extern crate cairo;
 #[cfg(feature = "gtk3")]
+extern crate gdk;
+#[cfg(feature = "gtk3")]
 extern crate gdk_pixbuf;
 #[cfg(feature = "gtk3")]
 extern crate glib;
@@ -55,20 +80,615 @@ extern crate pango;
 #[cfg(feature = "gtk3")]
 use lib_gtk as inner_imp;
 
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{cmp, error, fmt, mem};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ScalePolicy {
-    CropCenter, // TODO variants
-    FitCenter,  // TODO variants
-                // TODO Tile
+    /// Scale to cover the whole area keeping the aspect ratio, cut off the overflow at the end (right/bottom).
+    CropStart,
+    /// Scale to cover the whole area keeping the aspect ratio, cut off the overflow according to the gravity (evenly from both sides by default).
+    CropCenter,
+    /// Scale to cover the whole area keeping the aspect ratio, cut off the overflow at the start (left/top).
+    CropEnd,
+    /// Scale to cover the whole area keeping the aspect ratio, keep the crop window as close to the focal point as possible.
+    CropFocus,
+    /// Scale to cover the whole area keeping the aspect ratio, keep the crop window where the content has the most detail.
+    CropAuto,
+    /// Scale to fit inside the area keeping the aspect ratio, stick to the top-left corner.
+    FitStart,
+    /// Scale to fit inside the area keeping the aspect ratio, align according to the gravity (centered by default).
+    FitCenter,
+    /// Scale by the largest whole-number factor that fits, with nearest-neighbour sampling, align according to the gravity.
+    /// Content larger than the area is drawn at 1:1 centered, like `None`.
+    FitInteger,
+    /// Scale to fit inside the area keeping the aspect ratio, stick to the bottom-right corner.
+    FitEnd,
+    /// Scale each axis independently to fill the whole area.
+    Stretch,
+    /// Draw at 1:1 aligned according to the gravity, cutting off what does not fit.
+    None,
+    /// Draw at 1:1 repeatedly to fill the whole area, the first tile starting at `offset` from the top-left corner.
+    Tile { mode: TileMode, offset: (i32, i32) },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileMode {
+    /// Every tile is an exact copy of the content.
+    Repeat,
+    /// Every other tile is mirrored, so the tiles meet seamlessly.
+    MirroredRepeat,
+    /// The content is drawn once, its edge pixels are extended to the rest of the area.
+    ClampToEdge,
+}
+
+/// Where the content sticks to inside the area when it does not cover the area, or which part stays visible when it is cropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gravity {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+/// Resampling filter used when the content is drawn not at 1:1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Blocky, but keeps pixel art crisp.
+    Nearest,
+    Bilinear,
+    Bicubic,
+    /// Sharpest and slowest.
+    Lanczos,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NinePatchFill {
+    Stretch,
+    Tile,
+}
+
+/// Nine-slice insets: the corners outside of them are drawn at 1:1, the edges and the center between them are filled with `fill`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NinePatch {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+    pub fill: NinePatchFill,
+}
+
+impl NinePatch {
+    pub fn new(left: u32, top: u32, right: u32, bottom: u32, fill: NinePatchFill) -> NinePatch {
+        NinePatch { left, top, right, bottom, fill }
+    }
+    /// Reads the insets from the 1px border of an Android-style `.9.png`.
+    /// Returns them together with the content stripped of the border, or `None` if there are no border markers.
+    pub fn from_nine_png(content: &image::DynamicImage) -> Option<(NinePatch, image::DynamicImage)> {
+        render::parse_nine_png(&content.to_rgba()).map(|(nine_patch, content)| (nine_patch, image::DynamicImage::ImageRgba8(content)))
+    }
+}
+
+/// What is shown while the image has no content.
+#[derive(Debug, Clone)]
+pub enum Placeholder {
+    /// Fills the whole content area.
+    Color(image::Rgba<u8>),
+    /// Drawn with the same scale policy, gravity and interpolation the content would be, the image wraps to its size.
+    Image(image::RgbaImage),
+}
+
+/// Mirroring of the content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flip {
+    None,
+    /// Left and right swapped.
+    Horizontal,
+    /// Top and bottom swapped.
+    Vertical,
+    /// Both swapped, which looks the same as a half turn.
+    Both,
+}
+
+/// How the stored pixels are turned to show the picture upright, as cameras record it in the EXIF orientation tag.
+/// The rotations are clockwise, the mirroring comes before the rotation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Normal,
+    FlipHorizontal,
+    Rotate180,
+    FlipVertical,
+    /// Mirrored horizontally, then rotated by 270 degrees.
+    Transpose,
+    Rotate90,
+    /// Mirrored horizontally, then rotated by 90 degrees.
+    Transverse,
+    Rotate270,
+}
+
+impl Orientation {
+    /// Orientation of the EXIF tag value, 1 to 8, `None` for any other value.
+    pub fn from_exif(value: u16) -> Option<Orientation> {
+        match value {
+            1 => Some(Orientation::Normal),
+            2 => Some(Orientation::FlipHorizontal),
+            3 => Some(Orientation::Rotate180),
+            4 => Some(Orientation::FlipVertical),
+            5 => Some(Orientation::Transpose),
+            6 => Some(Orientation::Rotate90),
+            7 => Some(Orientation::Transverse),
+            8 => Some(Orientation::Rotate270),
+            _ => None,
+        }
+    }
+}
+
+/// Part of the content in pixels, `x`/`y` being the top-left corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// The sprite the region was trimmed from, shown in its place.
+    pub trim: Option<Trim>,
+}
+
+impl Region {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Region {
+        Region { x, y, width, height, trim: None }
+    }
+    pub fn with_trim(mut self, trim: Trim) -> Region {
+        self.trim = Some(trim);
+        self
+    }
+}
+
+/// Size of a sprite whose transparent edges were trimmed away when packed into a sprite sheet,
+/// `x`/`y` being where the trimmed region lies within it. The edges are drawn transparent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Trim {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Limits on top of the layout width and height, applied when the image is measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SizeConstraints {
+    pub min_width: Option<u16>,
+    pub min_height: Option<u16>,
+    pub max_width: Option<u16>,
+    pub max_height: Option<u16>,
+    /// Never give the content more pixels than its natural size.
+    pub no_upscale: bool,
+    /// Shrink one of the axes if needed, so the measured content area keeps the content aspect ratio.
+    pub lock_aspect_ratio: bool,
+}
+
+/// Decoded RGBA pixels, reference-counted so any number of images can show them without copying.
+/// Animated content holds all its frames, the pixels being the ones of the first frame.
+/// Vector content holds the picture it is rasterized from, the pixels being its raster at the natural size.
+/// Content made for HiDPI screens has a density above 1 and may hold variants of other densities, see `with_variants`.
+#[derive(Debug, Clone)]
+pub struct ImageSource {
+    pixels: Arc<image::RgbaImage>,
+    frames: Option<Arc<Vec<(ImageSource, Duration)>>>,
+    #[cfg(feature = "svg")]
+    vector: Option<Arc<vector::Vector>>,
+    density: f32,
+    orientation: Orientation,
+    /// The denser variants, this source being the least dense one.
+    variants: Option<Arc<Vec<ImageSource>>>,
+}
+
+impl ImageSource {
+    /// Takes the pixels as they are, bypassing the `cache`.
+    pub fn new(pixels: image::RgbaImage) -> ImageSource {
+        ImageSource {
+            pixels: Arc::new(pixels),
+            frames: None,
+            #[cfg(feature = "svg")]
+            vector: None,
+            density: 1.0,
+            orientation: Orientation::Normal,
+            variants: None,
+        }
+    }
+    /// SVG content, drawn sharp at any size it is shown at instead of being scaled, unless it is tiled or nine-patched.
+    #[cfg(feature = "svg")]
+    pub fn from_svg(svg: &[u8]) -> Result<ImageSource, ImageError> {
+        let (vector, tree) = vector::Vector::parse(svg)?;
+        let (width, height) = vector.size();
+        load::check_size(width, height)?;
+        Ok(ImageSource {
+            pixels: Arc::new(tree.rasterize([1.0, 0.0, 0.0, 1.0], (0.0, 0.0), width, height)),
+            frames: None,
+            vector: Some(Arc::new(vector)),
+            density: 1.0,
+            orientation: Orientation::Normal,
+            variants: None,
+        })
+    }
+    /// The same content marked as made for screens of `density` device pixels per logical pixel, e.g. 2 for `@2x` files.
+    /// It is shown at its pixel size divided by the density.
+    pub fn with_density(mut self, density: f32) -> ImageSource {
+        assert!(density > 0.0, "density must be positive");
+        self.density = density;
+        self
+    }
+    /// Device pixels per logical pixel the content is made for, 1 by default.
+    pub fn density(&self) -> f32 {
+        self.density
+    }
+    /// The same content marked as stored in `orientation`, which images turn upright unless told otherwise.
+    /// Decoded files get the orientation of their EXIF data.
+    pub fn with_orientation(mut self, orientation: Orientation) -> ImageSource {
+        self.orientation = orientation;
+        self
+    }
+    /// `Orientation::Normal` by default.
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+    /// Resolution variants of one picture, of which every image shows the one fitting its screen best.
+    /// The least dense variant defines the size the content is laid out at and the pixels regions are given in.
+    /// There must be at least one variant.
+    pub fn with_variants(mut variants: Vec<ImageSource>) -> ImageSource {
+        assert!(!variants.is_empty(), "resolution variants without any variant");
+        for variant in &mut variants {
+            variant.variants = None;
+        }
+        variants.sort_by(|a, b| a.density.partial_cmp(&b.density).unwrap_or(cmp::Ordering::Equal));
+        // the least dense variant is not kept among the others, so its pixels are shared only with what shows them
+        let mut source = variants.remove(0);
+        if !variants.is_empty() {
+            source.variants = Some(Arc::new(variants));
+        }
+        source
+    }
+    /// Variant to show on a screen of `scale_factor` device pixels per logical pixel:
+    /// the least dense one being at least that dense, or the densest one.
+    pub fn variant(&self, scale_factor: f32) -> &ImageSource {
+        match self.variants {
+            Some(ref variants) if self.density < scale_factor => variants.iter().find(|variant| variant.density >= scale_factor).unwrap_or_else(|| &variants[variants.len() - 1]),
+            _ => self,
+        }
+    }
+    /// Size the content is laid out at, the pixel size divided by the density, rounded up.
+    pub fn logical_size(&self) -> (u32, u32) {
+        let (width, height) = self.dimensions();
+        (logical(width, self.density), logical(height, self.density))
+    }
+    /// Whether the content is drawn from a vector picture rather than from its pixels.
+    pub fn is_vector(&self) -> bool {
+        #[cfg(feature = "svg")]
+        {
+            self.vector.is_some()
+        }
+        #[cfg(not(feature = "svg"))]
+        {
+            false
+        }
+    }
+    #[cfg(feature = "svg")]
+    fn vector(&self) -> Option<&Arc<vector::Vector>> {
+        self.vector.as_ref()
+    }
+    /// Animated content showing each frame for its delay. The frames must be of the same size, and there must be at least one.
+    pub fn animated(frames: Vec<(image::RgbaImage, Duration)>) -> ImageSource {
+        assert!(!frames.is_empty(), "animated content without frames");
+        let frames = frames.into_iter().map(|(pixels, delay)| (ImageSource::new(pixels), delay)).collect::<Vec<_>>();
+        ImageSource {
+            pixels: frames[0].0.pixels.clone(),
+            frames: if frames.len() > 1 { Some(Arc::new(frames)) } else { None },
+            #[cfg(feature = "svg")]
+            vector: None,
+            density: 1.0,
+            orientation: Orientation::Normal,
+            variants: None,
+        }
+    }
+    /// 1 for still content.
+    pub fn frame_count(&self) -> usize {
+        self.frames.as_ref().map_or(1, |frames| frames.len())
+    }
+    /// Still content of the frame at `index`, the content itself if it is not animated.
+    pub fn frame(&self, index: usize) -> Option<ImageSource> {
+        match self.frames {
+            Some(ref frames) => frames.get(index).map(|&(ref frame, _)| frame.clone()),
+            None if index == 0 => Some(self.clone()),
+            None => None,
+        }
+    }
+    /// How long the frame at `index` is shown, `None` for still content.
+    pub fn frame_delay(&self, index: usize) -> Option<Duration> {
+        self.frames.as_ref().and_then(|frames| frames.get(index)).map(|&(_, delay)| delay)
+    }
+    /// Decoded bytes of all the frames of all the variants.
+    pub fn byte_size(&self) -> usize {
+        let variants = self.variants.as_ref().map_or(0, |variants| variants.iter().map(ImageSource::byte_size).sum());
+        variants + match self.frames {
+            Some(ref frames) => frames.iter().map(|&(ref frame, _)| frame.pixels.len()).sum(),
+            None => self.pixels.len(),
+        }
+    }
+    /// Converts `content` to RGBA, bypassing the `cache`: telling the same pixels apart would take hashing all of them.
+    /// Clone the source to show the pixels in more than one image without copying them.
+    pub fn from_content(content: image::DynamicImage) -> ImageSource {
+        ImageSource::new(load::into_rgba(content))
+    }
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.pixels.dimensions()
+    }
+    pub fn pixels(&self) -> &image::RgbaImage {
+        &self.pixels
+    }
+    /// The pixels to write to, copied first if other sources share them. `None` for animated content.
+    /// Vector content becomes its raster, as the pixels written would not be in the picture,
+    /// and the other resolution variants are dropped, as they would not have them either.
+    pub fn pixels_mut(&mut self) -> Option<&mut image::RgbaImage> {
+        if self.frames.is_some() {
+            return None;
+        }
+        #[cfg(feature = "svg")]
+        {
+            self.vector = None;
+        }
+        self.variants = None;
+        Some(Arc::make_mut(&mut self.pixels))
+    }
+    /// Whether both sources share one buffer.
+    pub fn ptr_eq(&self, other: &ImageSource) -> bool {
+        Arc::ptr_eq(&self.pixels, &other.pixels)
+    }
+    /// Amount of sources sharing the buffer, or the frames of animated content, the `cache` entry included.
+    pub fn ref_count(&self) -> usize {
+        match self.frames {
+            Some(ref frames) => Arc::strong_count(frames),
+            None => Arc::strong_count(&self.pixels),
+        }
+    }
+    /// The pixels, moved out if this is the only source of them, the source itself otherwise.
+    pub fn into_pixels(mut self) -> Result<image::RgbaImage, ImageSource> {
+        if self.frames.is_none() {
+            if let Some(pixels) = Arc::get_mut(&mut self.pixels) {
+                return Ok(mem::replace(pixels, image::RgbaImage::new(0, 0)));
+            }
+        }
+        Err(self)
+    }
+    /// The raw RGBA bytes, moved out if this is the only source of them, copied otherwise.
+    pub fn into_raw(self) -> Vec<u8> {
+        match Arc::try_unwrap(self.pixels) {
+            Ok(pixels) => pixels.into_raw(),
+            Err(pixels) => pixels.to_vec(),
+        }
+    }
+}
+
+/// `pixels` of content of `density` in logical pixels, rounded up.
+fn logical(pixels: u32, density: f32) -> u32 {
+    (pixels as f32 / density).ceil() as u32
+}
+
+impl AsRef<[u8]> for ImageSource {
+    fn as_ref(&self) -> &[u8] {
+        &self.pixels
+    }
+}
+
+pub mod callbacks {
+    use super::{Image, ImageError};
+
+    /// Fired on the UI thread once the content loaded in the background is shown.
+    pub struct Load(Box<FnMut(&mut Image)>);
+    /// Fired on the UI thread if the content could not be loaded in the background.
+    pub struct LoadError(Box<FnMut(&mut Image, &ImageError)>);
+    /// Fired every time the animation moves on to another frame, with the index of that frame.
+    pub struct Frame(Box<FnMut(&mut Image, usize)>);
+
+    impl<F> From<F> for Load
+    where
+        F: FnMut(&mut Image) + 'static,
+    {
+        fn from(f: F) -> Load {
+            Load(Box::new(f))
+        }
+    }
+    impl AsMut<FnMut(&mut Image)> for Load {
+        fn as_mut(&mut self) -> &mut (FnMut(&mut Image) + 'static) {
+            self.0.as_mut()
+        }
+    }
+
+    impl<F> From<F> for LoadError
+    where
+        F: FnMut(&mut Image, &ImageError) + 'static,
+    {
+        fn from(f: F) -> LoadError {
+            LoadError(Box::new(f))
+        }
+    }
+    impl AsMut<FnMut(&mut Image, &ImageError)> for LoadError {
+        fn as_mut(&mut self) -> &mut (FnMut(&mut Image, &ImageError) + 'static) {
+            self.0.as_mut()
+        }
+    }
+
+    impl<F> From<F> for Frame
+    where
+        F: FnMut(&mut Image, usize) + 'static,
+    {
+        fn from(f: F) -> Frame {
+            Frame(Box::new(f))
+        }
+    }
+    impl AsMut<FnMut(&mut Image, usize)> for Frame {
+        fn as_mut(&mut self) -> &mut (FnMut(&mut Image, usize) + 'static) {
+            self.0.as_mut()
+        }
+    }
 }
 
 pub trait Image: plygui_api::controls::Control {
     fn set_scale(&mut self, policy: ScalePolicy);
     fn scale(&self) -> ScalePolicy;
+    /// When set, the nine-slice scaling is used instead of the scale policy.
+    fn set_nine_patch(&mut self, nine_patch: Option<NinePatch>);
+    fn nine_patch(&self) -> Option<NinePatch>;
+    /// Normalized (0..1, 0..1) point of the content used by `ScalePolicy::CropFocus`, (0.5, 0.5) by default.
+    fn set_focal_point(&mut self, x: f32, y: f32);
+    fn focal_point(&self) -> (f32, f32);
+    fn set_interpolation(&mut self, interpolation: Interpolation);
+    fn interpolation(&self) -> Interpolation;
+    /// Whole-number factor the content is currently drawn with, if the scale policy is `ScalePolicy::FitInteger`.
+    fn integer_scale(&self) -> Option<u32>;
+    fn set_size_constraints(&mut self, constraints: SizeConstraints);
+    fn size_constraints(&self) -> SizeConstraints;
+    /// Alignment of the `*Center`, `FitInteger` and `None` scale policies, `Gravity::Center` by default.
+    fn set_gravity(&mut self, gravity: Gravity);
+    fn gravity(&self) -> Gravity;
+    /// Replaces the shown pixels, keeping all the other settings.
+    /// Use `set_source` with a clone of one source to show the same pixels in more images without copying them.
+    fn set_content(&mut self, content: image::DynamicImage);
+    /// Same as `set_content`, but shows the given buffer, without copying it.
+    fn set_source(&mut self, source: ImageSource);
+    fn source(&self) -> Option<&ImageSource>;
+    fn content(&self) -> Option<&image::RgbaImage>;
+    fn clear_content(&mut self);
+    /// Shown instead of the content while there is none, nothing by default.
+    fn set_placeholder(&mut self, placeholder: Option<Placeholder>);
+    fn placeholder(&self) -> Option<&Placeholder>;
+    /// Decodes the file at `path` on a worker thread, showing the placeholder meanwhile.
+    /// The content is swapped in on the UI thread, then `on_load` is fired, or `on_error` if it could not be loaded.
+    /// Setting or clearing the content cancels the loading.
+    fn load_path(&mut self, path: PathBuf);
+    /// Same as `load_path`, but decodes an in-memory encoded image.
+    fn load_bytes(&mut self, bytes: Vec<u8>);
+    fn is_loading(&self) -> bool;
+    fn on_load(&mut self, callback: Option<callbacks::Load>);
+    fn on_error(&mut self, callback: Option<callbacks::LoadError>);
+    /// Resumes the animation of animated content, starting over if it has finished. Animations play by default.
+    /// The animation stays paused while the image is not `Visibility::Visible`.
+    /// Animated GIF and APNG content plays. Animated WebP is not supported, loading it fails with `ImageError::Decode`.
+    fn play(&mut self);
+    fn pause(&mut self);
+    fn is_playing(&self) -> bool;
+    /// Shows the frame at `frame`, clamped to the last one, the current frame being shown its full delay again.
+    fn seek(&mut self, frame: usize);
+    fn current_frame(&self) -> usize;
+    /// How many times the animation plays before stopping on its last frame, `None` (forever) by default.
+    /// `Some(0)` shows the first frame without playing. Raising the count of an animation the count before had
+    /// stopped plays it on from the first frame.
+    fn set_loop_count(&mut self, count: Option<u32>);
+    fn loop_count(&self) -> Option<u32>;
+    fn on_frame(&mut self, callback: Option<callbacks::Frame>);
+    /// Shows only the given part of the content, the scale policies applying to that part as if it was the whole content.
+    /// The region is kept when the content changes, and is cut down to the content bounds when drawn.
+    fn set_region(&mut self, region: Option<Region>);
+    fn region(&self) -> Option<Region>;
+    /// Shows the region of `atlas` called `name`, sharing the atlas buffer. Returns `false` if there is no such region.
+    fn set_atlas_region(&mut self, atlas: &atlas::Atlas, name: &str) -> bool;
+    /// Connects a sink any thread can push frames into, each new frame replacing the content on the UI thread.
+    /// The sink connected before is closed.
+    fn frame_sink(&mut self) -> sink::FrameSink;
+    /// Writes `pixels` over the content at (`x`, `y`), redrawing only the part of the image showing them.
+    /// What does not fit into the content is left out. Returns `false` if there is no still content to write to.
+    fn update_pixels(&mut self, x: u32, y: u32, pixels: &image::RgbaImage) -> bool;
+    /// Turns the content upright according to the orientation it is stored in, see `ImageSource::orientation`. On by default.
+    fn set_auto_orientation(&mut self, enabled: bool);
+    fn auto_orientation(&self) -> bool;
+    /// Rotates the content clockwise by `degrees` after turning it upright and flipping it, 0 by default.
+    /// Quarter turns move the pixels as they are, any other angle resamples them with the interpolation, leaving the corners transparent.
+    /// The image is measured and the scale policies are applied around the rotated bounds.
+    /// Regions and `update_pixels` still address the pixels as they are stored.
+    fn set_rotation(&mut self, degrees: f32);
+    /// Within 0..360.
+    fn rotation(&self) -> f32;
+    /// Mirrors the content after turning it upright, `Flip::None` by default.
+    fn set_flip(&mut self, flip: Flip);
+    fn flip(&self) -> Flip;
+}
+
+/// Reasons the content could not be loaded.
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    /// The data is not in any of the supported formats.
+    UnsupportedFormat,
+    /// The data is in a supported format, but is corrupted or uses an unsupported feature, such as animated WebP.
+    Decode(String),
+    /// The content has more pixels than allowed to be decoded.
+    TooLarge { width: u32, height: u32 },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImageError::Io(ref e) => write!(f, "Could not read image: {}", e),
+            ImageError::UnsupportedFormat => write!(f, "Unsupported image format"),
+            ImageError::Decode(ref e) => write!(f, "Could not decode image: {}", e),
+            ImageError::TooLarge { width, height } => write!(f, "Image is too large: {}x{}", width, height),
+        }
+    }
+}
+
+impl error::Error for ImageError {
+    fn description(&self) -> &str {
+        match *self {
+            ImageError::Io(ref e) => e.description(),
+            ImageError::UnsupportedFormat => "unsupported image format",
+            ImageError::Decode(ref e) => e.as_str(),
+            ImageError::TooLarge { .. } => "image is too large",
+        }
+    }
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            ImageError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ImageError {
+    fn from(e: io::Error) -> ImageError {
+        ImageError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for ImageError {
+    fn from(e: image::ImageError) -> ImageError {
+        match e {
+            image::ImageError::IoError(e) => ImageError::Io(e),
+            image::ImageError::UnsupportedError(_) => ImageError::UnsupportedFormat,
+            e => ImageError::Decode(e.to_string()),
+        }
+    }
 }
 
 pub trait NewImage {
     fn with_content(content: image::DynamicImage) -> Box<Image>;
+    fn with_source(source: ImageSource) -> Box<Image>;
+    /// Image without content, showing the placeholder until the content is set.
+    fn empty() -> Box<Image>;
+    /// Reads and decodes the file at `path` if it is not in the `cache` yet, the format is detected from the data.
+    fn from_path<P: AsRef<Path>>(path: P) -> Result<Box<Image>, ImageError>;
+    /// Decodes an in-memory encoded image if it is not in the `cache` yet, the format is detected from the data.
+    fn from_bytes(bytes: &[u8]) -> Result<Box<Image>, ImageError>;
+    /// Reads `reader` to the end and decodes the data, the format is detected from the data.
+    fn from_reader<R: Read>(reader: R) -> Result<Box<Image>, ImageError>;
+    /// Image showing the region of `atlas` called `name`, `None` if there is no such region.
+    fn from_atlas(atlas: &atlas::Atlas, name: &str) -> Option<Box<Image>>;
 }
 
 pub mod imp {
@@ -77,11 +697,58 @@ pub mod imp {
 
 pub mod development {
     use plygui_api::development::*;
+    use plygui_api::layout;
+
+    use super::geometry::Rect;
+    use super::render::State;
+    use super::{animation, load, sink, transform};
+
+    use std::io::Read;
+    use std::path::{Path, PathBuf};
+    use std::time::Instant;
 
     pub trait ImageInner: ControlInner {
-        fn with_content(content: super::image::DynamicImage) -> Box<super::Image>;
+        fn with_source(source: super::ImageSource) -> Box<super::Image>;
+        fn empty() -> Box<super::Image>;
         fn set_scale(&mut self, member: &mut MemberBase, control: &mut ControlBase, policy: super::ScalePolicy);
         fn scale(&self) -> super::ScalePolicy;
+        fn set_nine_patch(&mut self, member: &mut MemberBase, control: &mut ControlBase, nine_patch: Option<super::NinePatch>);
+        fn nine_patch(&self) -> Option<super::NinePatch>;
+        fn set_focal_point(&mut self, member: &mut MemberBase, control: &mut ControlBase, x: f32, y: f32);
+        fn focal_point(&self) -> (f32, f32);
+        fn set_interpolation(&mut self, member: &mut MemberBase, control: &mut ControlBase, interpolation: super::Interpolation);
+        fn interpolation(&self) -> super::Interpolation;
+        fn integer_scale(&self) -> Option<u32>;
+        fn set_size_constraints(&mut self, member: &mut MemberBase, control: &mut ControlBase, constraints: super::SizeConstraints);
+        fn size_constraints(&self) -> super::SizeConstraints;
+        fn set_gravity(&mut self, member: &mut MemberBase, control: &mut ControlBase, gravity: super::Gravity);
+        fn gravity(&self) -> super::Gravity;
+        fn set_source(&mut self, member: &mut MemberBase, control: &mut ControlBase, source: Option<super::ImageSource>);
+        fn source(&self) -> Option<&super::ImageSource>;
+        fn set_placeholder(&mut self, member: &mut MemberBase, control: &mut ControlBase, placeholder: Option<super::Placeholder>);
+        fn placeholder(&self) -> Option<&super::Placeholder>;
+        fn load(&mut self, member: &mut MemberBase, control: &mut ControlBase, decode: Box<FnMut() -> Result<super::ImageSource, super::ImageError> + Send>);
+        fn is_loading(&self) -> bool;
+        fn on_load(&mut self, callback: Option<super::callbacks::Load>);
+        fn on_error(&mut self, callback: Option<super::callbacks::LoadError>);
+        fn play(&mut self, member: &mut MemberBase, control: &mut ControlBase);
+        fn pause(&mut self, member: &mut MemberBase, control: &mut ControlBase);
+        fn is_playing(&self) -> bool;
+        fn seek(&mut self, member: &mut MemberBase, control: &mut ControlBase, frame: usize);
+        fn current_frame(&self) -> usize;
+        fn set_loop_count(&mut self, member: &mut MemberBase, control: &mut ControlBase, count: Option<u32>);
+        fn loop_count(&self) -> Option<u32>;
+        fn on_frame(&mut self, callback: Option<super::callbacks::Frame>);
+        fn set_region(&mut self, member: &mut MemberBase, control: &mut ControlBase, region: Option<super::Region>);
+        fn region(&self) -> Option<super::Region>;
+        fn frame_sink(&mut self, member: &mut MemberBase, control: &mut ControlBase) -> super::sink::FrameSink;
+        fn update_pixels(&mut self, member: &mut MemberBase, control: &mut ControlBase, x: u32, y: u32, pixels: &super::image::RgbaImage) -> bool;
+        fn set_auto_orientation(&mut self, member: &mut MemberBase, control: &mut ControlBase, enabled: bool);
+        fn auto_orientation(&self) -> bool;
+        fn set_rotation(&mut self, member: &mut MemberBase, control: &mut ControlBase, degrees: f32);
+        fn rotation(&self) -> f32;
+        fn set_flip(&mut self, member: &mut MemberBase, control: &mut ControlBase, flip: super::Flip);
+        fn flip(&self) -> super::Flip;
     }
 
     impl<T: ImageInner + Sized + 'static> super::Image for Member<Control<T>> {
@@ -93,10 +760,501 @@ pub mod development {
         fn scale(&self) -> super::ScalePolicy {
             self.as_inner().as_inner().scale()
         }
+        fn set_nine_patch(&mut self, nine_patch: Option<super::NinePatch>) {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().set_nine_patch(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() }, nine_patch)
+        }
+        fn nine_patch(&self) -> Option<super::NinePatch> {
+            self.as_inner().as_inner().nine_patch()
+        }
+        fn set_focal_point(&mut self, x: f32, y: f32) {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().set_focal_point(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() }, x, y)
+        }
+        fn focal_point(&self) -> (f32, f32) {
+            self.as_inner().as_inner().focal_point()
+        }
+        fn set_interpolation(&mut self, interpolation: super::Interpolation) {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().set_interpolation(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() }, interpolation)
+        }
+        fn interpolation(&self) -> super::Interpolation {
+            self.as_inner().as_inner().interpolation()
+        }
+        fn integer_scale(&self) -> Option<u32> {
+            self.as_inner().as_inner().integer_scale()
+        }
+        fn set_size_constraints(&mut self, constraints: super::SizeConstraints) {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().set_size_constraints(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() }, constraints)
+        }
+        fn size_constraints(&self) -> super::SizeConstraints {
+            self.as_inner().as_inner().size_constraints()
+        }
+        fn set_gravity(&mut self, gravity: super::Gravity) {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().set_gravity(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() }, gravity)
+        }
+        fn gravity(&self) -> super::Gravity {
+            self.as_inner().as_inner().gravity()
+        }
+        fn set_content(&mut self, content: super::image::DynamicImage) {
+            self.set_source(super::ImageSource::from_content(content))
+        }
+        fn set_source(&mut self, source: super::ImageSource) {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().set_source(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() }, Some(source))
+        }
+        fn source(&self) -> Option<&super::ImageSource> {
+            self.as_inner().as_inner().source()
+        }
+        fn content(&self) -> Option<&super::image::RgbaImage> {
+            self.as_inner().as_inner().source().map(super::ImageSource::pixels)
+        }
+        fn clear_content(&mut self) {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().set_source(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() }, None)
+        }
+        fn set_placeholder(&mut self, placeholder: Option<super::Placeholder>) {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().set_placeholder(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() }, placeholder)
+        }
+        fn placeholder(&self) -> Option<&super::Placeholder> {
+            self.as_inner().as_inner().placeholder()
+        }
+        fn load_path(&mut self, path: PathBuf) {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().load(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() }, Box::new(move || super::cache::from_path(&path)))
+        }
+        fn load_bytes(&mut self, bytes: Vec<u8>) {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().load(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() }, Box::new(move || super::cache::from_bytes(&bytes)))
+        }
+        fn is_loading(&self) -> bool {
+            self.as_inner().as_inner().is_loading()
+        }
+        fn on_load(&mut self, callback: Option<super::callbacks::Load>) {
+            self.as_inner_mut().as_inner_mut().on_load(callback)
+        }
+        fn on_error(&mut self, callback: Option<super::callbacks::LoadError>) {
+            self.as_inner_mut().as_inner_mut().on_error(callback)
+        }
+        fn play(&mut self) {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().play(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() })
+        }
+        fn pause(&mut self) {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().pause(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() })
+        }
+        fn is_playing(&self) -> bool {
+            self.as_inner().as_inner().is_playing()
+        }
+        fn seek(&mut self, frame: usize) {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().seek(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() }, frame)
+        }
+        fn current_frame(&self) -> usize {
+            self.as_inner().as_inner().current_frame()
+        }
+        fn set_loop_count(&mut self, count: Option<u32>) {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().set_loop_count(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() }, count)
+        }
+        fn loop_count(&self) -> Option<u32> {
+            self.as_inner().as_inner().loop_count()
+        }
+        fn on_frame(&mut self, callback: Option<super::callbacks::Frame>) {
+            self.as_inner_mut().as_inner_mut().on_frame(callback)
+        }
+        fn set_region(&mut self, region: Option<super::Region>) {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().set_region(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() }, region)
+        }
+        fn region(&self) -> Option<super::Region> {
+            self.as_inner().as_inner().region()
+        }
+        fn set_atlas_region(&mut self, atlas: &super::atlas::Atlas, name: &str) -> bool {
+            let region = match atlas.region(name) {
+                Some(region) => region,
+                None => return false,
+            };
+            if !self.source().map_or(false, |source| source.ptr_eq(atlas.source())) {
+                self.set_source(atlas.source().clone());
+            }
+            self.set_region(Some(region));
+            true
+        }
+        fn frame_sink(&mut self) -> super::sink::FrameSink {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().frame_sink(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() })
+        }
+        fn update_pixels(&mut self, x: u32, y: u32, pixels: &super::image::RgbaImage) -> bool {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().update_pixels(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() }, x, y, pixels)
+        }
+        fn set_auto_orientation(&mut self, enabled: bool) {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().set_auto_orientation(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() }, enabled)
+        }
+        fn auto_orientation(&self) -> bool {
+            self.as_inner().as_inner().auto_orientation()
+        }
+        fn set_rotation(&mut self, degrees: f32) {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().set_rotation(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() }, degrees)
+        }
+        fn rotation(&self) -> f32 {
+            self.as_inner().as_inner().rotation()
+        }
+        fn set_flip(&mut self, flip: super::Flip) {
+            let base1 = self as *mut _ as *mut Member<Control<T>>;
+            let base2 = self as *mut _ as *mut Member<Control<T>>;
+            self.as_inner_mut().as_inner_mut().set_flip(unsafe { (&mut *base1).base_mut() }, unsafe { (&mut *base2).as_inner_mut().base_mut() }, flip)
+        }
+        fn flip(&self) -> super::Flip {
+            self.as_inner().as_inner().flip()
+        }
+    }
+    /// What every backend does with its own widget. The backends keep the rest of the image in a `State`,
+    /// and `ImageInner` is implemented on top of both alike for all of them.
+    pub trait ImageBackend: ControlInner + Sized + 'static {
+        /// Frame converted for the toolkit.
+        type Native;
+
+        fn new(source: Option<super::ImageSource>) -> Box<super::Image>;
+        fn state(&self) -> &State<Self::Native>;
+        fn state_mut(&mut self) -> &mut State<Self::Native>;
+        /// Size of the content area, in logical pixels.
+        fn inner_size(&self) -> (u32, u32);
+        /// Shows the frame of the current size, rendering it if it is not kept.
+        fn show(&mut self);
+        /// Redraws the `rect` part of the frame shown, the content having changed but not its size.
+        fn redraw(&mut self, rect: Rect);
+        /// Has the container lay the image out again.
+        fn invalidate_layout(&mut self);
+        /// Has `poll` called on a timer for as long as it returns `true`, unless the timer runs already.
+        fn start_polling(&mut self);
+        /// Has `advance` called once the next frame of the animation is due, instead of when it was due before.
+        fn schedule_frame(&mut self);
+
+        /// Size of the frames, the content area in device pixels.
+        fn frame_size(&self) -> (u32, u32) {
+            let (w, h) = self.inner_size();
+            let scale_factor = self.state().renderer.scale_factor();
+            ((w as f32 * scale_factor).round() as u32, (h as f32 * scale_factor).round() as u32)
+        }
+        /// Drops the frames rendered so far and shows a new one.
+        fn restyle(&mut self) {
+            self.state_mut().renditions.clear();
+            self.show();
+        }
+        fn on_content_changed(&mut self, control: &ControlBase) {
+            self.restyle();
+            if wraps_content(control) {
+                self.invalidate_layout();
+            }
+        }
+        /// Shows the current frame of the animation and times the next one.
+        fn show_frame(&mut self) {
+            {
+                let state = self.state_mut();
+                let frame = state.player.frame();
+                state.renderer.set_frame(frame);
+            }
+            self.restyle();
+            self.schedule_frame();
+        }
+        /// Shows a frame taken from the sink, redrawing the frame shown in place if the size has not changed.
+        fn show_live_frame(&mut self, frame: super::image::RgbaImage) {
+            let resized = {
+                let state = self.state_mut();
+                let resized = match state.sink {
+                    Some(ref receiver) => sink::show(&mut state.renderer, receiver, frame),
+                    None => return,
+                };
+                state.player.reset(None);
+                resized
+            };
+            self.schedule_frame();
+            if resized {
+                self.restyle();
+                self.invalidate_layout();
+                return;
+            }
+            let (w, h) = self.frame_size();
+            self.redraw(Rect::new(0, 0, w as i32, h as i32));
+        }
+        /// Shows what the background decoding and the frame sink have got, `image` being the image of this backend.
+        /// Returns whether to keep polling.
+        fn poll(&mut self, image: &mut super::Image) -> bool {
+            {
+                let state = self.state_mut();
+                if let Some(result) = state.loader.poll() {
+                    load::deliver(image, &mut state.loader, result);
+                }
+            }
+            let frame = self.state().sink.as_ref().and_then(sink::Receiver::take);
+            if let Some(frame) = frame {
+                self.show_live_frame(frame);
+            }
+            let state = self.state();
+            state.loader.is_loading() || state.sink.is_some()
+        }
+        /// Moves the animation on if its next frame is due, `image` being the image of this backend.
+        fn advance(&mut self, image: &mut super::Image) {
+            if self.state_mut().player.advance(Instant::now()) {
+                self.show_frame();
+                animation::notify(image, &mut self.state_mut().player);
+            } else {
+                self.schedule_frame();
+            }
+        }
     }
+
+    impl<T: ImageBackend> ImageInner for T {
+        fn with_source(source: super::ImageSource) -> Box<super::Image> {
+            T::new(Some(source))
+        }
+        fn empty() -> Box<super::Image> {
+            T::new(None)
+        }
+        fn set_scale(&mut self, _: &mut MemberBase, _: &mut ControlBase, policy: super::ScalePolicy) {
+            if self.state().style.scale != policy {
+                self.state_mut().style.scale = policy;
+                self.restyle();
+            }
+        }
+        fn scale(&self) -> super::ScalePolicy {
+            self.state().style.scale
+        }
+        fn set_nine_patch(&mut self, _: &mut MemberBase, _: &mut ControlBase, nine_patch: Option<super::NinePatch>) {
+            if self.state().style.nine_patch != nine_patch {
+                self.state_mut().style.nine_patch = nine_patch;
+                self.restyle();
+            }
+        }
+        fn nine_patch(&self) -> Option<super::NinePatch> {
+            self.state().style.nine_patch
+        }
+        fn set_focal_point(&mut self, _: &mut MemberBase, _: &mut ControlBase, x: f32, y: f32) {
+            let focal_point = (x.max(0.0).min(1.0), y.max(0.0).min(1.0));
+            if self.state().style.focal_point != focal_point {
+                self.state_mut().style.focal_point = focal_point;
+                self.restyle();
+            }
+        }
+        fn focal_point(&self) -> (f32, f32) {
+            self.state().style.focal_point
+        }
+        fn set_interpolation(&mut self, _: &mut MemberBase, _: &mut ControlBase, interpolation: super::Interpolation) {
+            if self.state().style.interpolation != interpolation {
+                self.state_mut().style.interpolation = interpolation;
+                self.restyle();
+            }
+        }
+        fn interpolation(&self) -> super::Interpolation {
+            self.state().style.interpolation
+        }
+        fn integer_scale(&self) -> Option<u32> {
+            let (w, h) = self.inner_size();
+            let state = self.state();
+            state.renderer.integer_scale(&state.style, w, h)
+        }
+        fn set_size_constraints(&mut self, _: &mut MemberBase, _: &mut ControlBase, constraints: super::SizeConstraints) {
+            if self.state().constraints != constraints {
+                self.state_mut().constraints = constraints;
+                self.invalidate_layout();
+            }
+        }
+        fn size_constraints(&self) -> super::SizeConstraints {
+            self.state().constraints
+        }
+        fn set_gravity(&mut self, _: &mut MemberBase, _: &mut ControlBase, gravity: super::Gravity) {
+            if self.state().style.gravity != gravity {
+                self.state_mut().style.gravity = gravity;
+                self.restyle();
+            }
+        }
+        fn gravity(&self) -> super::Gravity {
+            self.state().style.gravity
+        }
+        fn set_source(&mut self, _: &mut MemberBase, control: &mut ControlBase, source: Option<super::ImageSource>) {
+            self.state_mut().set_content(source);
+            self.on_content_changed(control);
+            self.schedule_frame();
+        }
+        fn source(&self) -> Option<&super::ImageSource> {
+            self.state().renderer.content()
+        }
+        fn set_placeholder(&mut self, _: &mut MemberBase, control: &mut ControlBase, placeholder: Option<super::Placeholder>) {
+            self.state_mut().renderer.set_placeholder(placeholder);
+            if self.state().renderer.content().is_none() {
+                self.on_content_changed(control);
+            }
+        }
+        fn placeholder(&self) -> Option<&super::Placeholder> {
+            self.state().renderer.placeholder()
+        }
+        fn load(&mut self, _: &mut MemberBase, control: &mut ControlBase, decode: load::Decode) {
+            self.state_mut().set_content(None);
+            self.on_content_changed(control);
+            self.schedule_frame();
+            self.state_mut().loader.start(decode);
+            self.start_polling();
+        }
+        fn is_loading(&self) -> bool {
+            self.state().loader.is_loading()
+        }
+        fn on_load(&mut self, callback: Option<super::callbacks::Load>) {
+            self.state_mut().loader.on_load = callback;
+        }
+        fn on_error(&mut self, callback: Option<super::callbacks::LoadError>) {
+            self.state_mut().loader.on_error = callback;
+        }
+        fn play(&mut self, _: &mut MemberBase, _: &mut ControlBase) {
+            self.state_mut().player.play();
+            self.show_frame();
+        }
+        fn pause(&mut self, _: &mut MemberBase, _: &mut ControlBase) {
+            self.state_mut().player.pause();
+            self.schedule_frame();
+        }
+        fn is_playing(&self) -> bool {
+            self.state().player.is_playing()
+        }
+        fn seek(&mut self, _: &mut MemberBase, _: &mut ControlBase, frame: usize) {
+            self.state_mut().player.seek(frame);
+            self.show_frame();
+        }
+        fn current_frame(&self) -> usize {
+            self.state().player.frame()
+        }
+        fn set_loop_count(&mut self, _: &mut MemberBase, _: &mut ControlBase, count: Option<u32>) {
+            self.state_mut().player.set_loop_count(count);
+            self.show_frame();
+        }
+        fn loop_count(&self) -> Option<u32> {
+            self.state().player.loop_count()
+        }
+        fn on_frame(&mut self, callback: Option<super::callbacks::Frame>) {
+            self.state_mut().player.on_frame = callback;
+        }
+        fn set_region(&mut self, _: &mut MemberBase, control: &mut ControlBase, region: Option<super::Region>) {
+            if self.state().renderer.region() != region {
+                self.state_mut().renderer.set_region(region);
+                self.on_content_changed(control);
+            }
+        }
+        fn region(&self) -> Option<super::Region> {
+            self.state().renderer.region()
+        }
+        fn frame_sink(&mut self, _: &mut MemberBase, _: &mut ControlBase) -> sink::FrameSink {
+            let (sink, receiver) = sink::channel();
+            self.state_mut().sink = Some(receiver);
+            self.start_polling();
+            sink
+        }
+        fn update_pixels(&mut self, _: &mut MemberBase, _: &mut ControlBase, x: u32, y: u32, pixels: &super::image::RgbaImage) -> bool {
+            let changed = match self.state_mut().renderer.update_pixels(x, y, pixels) {
+                Some(changed) => changed,
+                None => return false,
+            };
+            let (w, h) = self.frame_size();
+            let damage = {
+                let state = self.state();
+                state.renderer.damage(&state.style, changed, w, h)
+            };
+            match damage {
+                Some(damage) => self.redraw(damage),
+                // nothing shown has changed, but the frames kept for the other sizes are out of date
+                None => {
+                    self.state_mut().renditions.reuse((w, h));
+                }
+            }
+            true
+        }
+        fn set_auto_orientation(&mut self, _: &mut MemberBase, control: &mut ControlBase, enabled: bool) {
+            if self.state().renderer.auto_orientation() != enabled {
+                self.state_mut().renderer.set_auto_orientation(enabled);
+                self.on_content_changed(control);
+            }
+        }
+        fn auto_orientation(&self) -> bool {
+            self.state().renderer.auto_orientation()
+        }
+        fn set_rotation(&mut self, _: &mut MemberBase, control: &mut ControlBase, degrees: f32) {
+            if self.state().renderer.rotation() != transform::normalize(degrees) {
+                self.state_mut().renderer.set_rotation(degrees);
+                self.on_content_changed(control);
+            }
+        }
+        fn rotation(&self) -> f32 {
+            self.state().renderer.rotation()
+        }
+        fn set_flip(&mut self, _: &mut MemberBase, control: &mut ControlBase, flip: super::Flip) {
+            if self.state().renderer.flip() != flip {
+                self.state_mut().renderer.set_flip(flip);
+                self.on_content_changed(control);
+            }
+        }
+        fn flip(&self) -> super::Flip {
+            self.state().renderer.flip()
+        }
+    }
+
+    /// Whether the measured size depends on the content size.
+    pub fn wraps_content(control: &ControlBase) -> bool {
+        match (&control.layout.width, &control.layout.height) {
+            (&layout::Size::WrapContent, _) | (_, &layout::Size::WrapContent) => true,
+            _ => false,
+        }
+    }
+
     impl<T: ImageInner + Sized> super::NewImage for Member<Control<T>> {
         fn with_content(content: super::image::DynamicImage) -> Box<super::Image> {
-            T::with_content(content)
+            T::with_source(super::ImageSource::from_content(content))
+        }
+        fn with_source(source: super::ImageSource) -> Box<super::Image> {
+            T::with_source(source)
+        }
+        fn empty() -> Box<super::Image> {
+            T::empty()
+        }
+        fn from_path<P: AsRef<Path>>(path: P) -> Result<Box<super::Image>, super::ImageError> {
+            super::cache::from_path(path.as_ref()).map(T::with_source)
+        }
+        fn from_bytes(bytes: &[u8]) -> Result<Box<super::Image>, super::ImageError> {
+            super::cache::from_bytes(bytes).map(T::with_source)
+        }
+        fn from_reader<R: Read>(reader: R) -> Result<Box<super::Image>, super::ImageError> {
+            super::load::read(reader).and_then(|bytes| super::cache::from_bytes(&bytes)).map(T::with_source)
+        }
+        fn from_atlas(atlas: &super::atlas::Atlas, name: &str) -> Option<Box<super::Image>> {
+            atlas.region(name).map(|region| {
+                let mut image = T::with_source(atlas.source().clone());
+                image.set_region(Some(region));
+                image
+            })
         }
     }
 }
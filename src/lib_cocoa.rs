@@ -1,18 +1,24 @@
-use super::development as image_dev;
+use super::animation;
+use super::development::{self as image_dev, ImageBackend};
+use super::geometry::{self, Rect};
+use super::load;
+use super::pixels;
+use super::render::State;
 
 use plygui_cocoa::common::*;
 
-use plygui_cocoa::core_graphics::base::{kCGBitmapByteOrderDefault, kCGImageAlphaLast};
-use plygui_cocoa::core_graphics::color_space::CGColorSpace;
-use plygui_cocoa::core_graphics::data_provider::CGDataProvider;
-use plygui_cocoa::core_graphics::image::CGImage;
+use objc::runtime::{NO, YES};
 
-use std::sync::Arc;
+use std::ptr;
+use std::slice;
+use std::time::Instant;
 
 lazy_static! {
     static ref WINDOW_CLASS: common::RefClass = unsafe {
         common::register_window_class("PlyguiImage", BASE_CLASS, |decl| {
             decl.add_method(sel!(setFrameSize:), set_frame_size as extern "C" fn(&mut Object, Sel, NSSize));
+            decl.add_method(sel!(pollLoading:), poll_loading as extern "C" fn(&mut Object, Sel, cocoa_id));
+            decl.add_method(sel!(nextFrame:), next_frame as extern "C" fn(&mut Object, Sel, cocoa_id));
         })
     };
 }
@@ -26,46 +32,38 @@ pub type Image = Member<Control<ImageCocoa>>;
 pub struct ImageCocoa {
     base: common::CocoaControlBase<Image>,
 
-    img: cocoa_id,
+    state: State<NativeImage>,
+    load_timer: cocoa_id,
+    frame_timer: cocoa_id,
 }
 
-impl ImageCocoa {
-    fn install_image(&mut self, content: super::image::DynamicImage) {
-        use image::GenericImage;
-
-        let size = content.dimensions();
+/// NSImage drawing a rendered frame from a bitmap of its own, so the frame can be redrawn in place.
+/// Released when dropped, the view retains the one it shows.
+pub struct NativeImage {
+    image: cocoa_id,
+    bitmap: cocoa_id,
+}
 
+impl Drop for NativeImage {
+    fn drop(&mut self) {
         unsafe {
-            let color_space = CGColorSpace::create_device_rgb();
-            let provider = CGDataProvider::from_buffer(Arc::new(content.to_rgba().into_raw()));
-            let cgimage = CGImage::new(size.0 as usize, size.1 as usize, 8, 32, 4 * size.0 as usize, &color_space, kCGBitmapByteOrderDefault | kCGImageAlphaLast, &provider, true, 0);
-
-            self.img = msg_send![class!(NSImage), alloc];
-            let size = NSSize::new(size.0 as f64, size.1 as f64);
-            let () = msg_send![self.img, initWithCGImage:cgimage size:size];
-            let () = msg_send![self.base.control, setImage:self.img];
-        }
-    }
-    fn remove_image(&mut self) {
-        unsafe {
-            let () = msg_send![self.img, dealloc];
+            let () = msg_send![self.image, release];
+            let () = msg_send![self.bitmap, release];
         }
     }
 }
 
-impl Drop for ImageCocoa {
-    fn drop(&mut self) {
-        self.remove_image();
-    }
-}
+impl image_dev::ImageBackend for ImageCocoa {
+    type Native = NativeImage;
 
-impl image_dev::ImageInner for ImageCocoa {
-    fn with_content(content: super::image::DynamicImage) -> Box<super::Image> {
+    fn new(source: Option<super::ImageSource>) -> Box<super::Image> {
         let mut i = Box::new(Member::with_inner(
             Control::with_inner(
                 ImageCocoa {
                     base: common::CocoaControlBase::with_params(*WINDOW_CLASS),
-                    img: nil,
+                    state: State::new(source),
+                    load_timer: nil,
+                    frame_timer: nil,
                 },
                 (),
             ),
@@ -74,23 +72,109 @@ impl image_dev::ImageInner for ImageCocoa {
         let selfptr = i.as_mut() as *mut _ as *mut ::std::os::raw::c_void;
         unsafe {
             (&mut *i.as_inner_mut().as_inner_mut().base.control).set_ivar(common::IVAR, selfptr);
+            // NSImageScaleNone, NSImageAlignCenter
+            let () = msg_send![i.as_inner_mut().as_inner_mut().base.control, setImageScaling:2];
             let () = msg_send![i.as_inner_mut().as_inner_mut().base.control, setImageAlignment:0];
         }
-        i.as_inner_mut().as_inner_mut().install_image(content);
+        i.as_inner_mut().as_inner_mut().show();
+        i.as_inner_mut().as_inner_mut().schedule_frame();
         i
     }
-    fn set_scale(&mut self, _member: &mut MemberBase, _control: &mut ControlBase, policy: super::ScalePolicy) {
-        if self.scale() != policy {
-            let scale = policy_to_nsscale(policy);
+    fn state(&self) -> &State<NativeImage> {
+        &self.state
+    }
+    fn state_mut(&mut self) -> &mut State<NativeImage> {
+        &mut self.state
+    }
+    /// Renders the content for the current size, the view itself only shows it at 1:1.
+    fn inner_size(&self) -> (u32, u32) {
+        use std::cmp::max;
+
+        let (w, h) = self.base.size();
+        (max(1, w as i32 - DEFAULT_PADDING - DEFAULT_PADDING) as u32, max(1, h as i32 - DEFAULT_PADDING - DEFAULT_PADDING) as u32)
+    }
+    fn show(&mut self) {
+        let size = self.frame_size();
+        if let Some(image) = self.state.frame(size, native_image_from_source) {
+            unsafe {
+                let () = msg_send![self.base.control, setImage:image.image];
+            }
+        }
+    }
+    /// Redraws the `rect` part of the bitmap shown in place, and has only the part of the view showing it drawn again.
+    /// Shows a new image if the one shown is of another size.
+    fn redraw(&mut self, rect: Rect) {
+        let (w, h) = self.frame_size();
+        let state = &mut self.state;
+        let (renderer, style) = (&mut state.renderer, &state.style);
+        let redrawn = match state.renditions.reuse((w, h)) {
+            Some(image) => unsafe {
+                let data: *mut u8 = msg_send![image.bitmap, bitmapData];
+                let bytes = slice::from_raw_parts_mut(data, pixels::BITMAP_REP.stride(w) * h as usize);
+                pixels::write_rect(&renderer.render_rect(style, w, h, rect), rect, pixels::BITMAP_REP, bytes, (w, h));
+                // drops what the image has cached of the bitmap
+                let () = msg_send![image.image, recache];
+                true
+            },
+            None => false,
+        };
+        if !redrawn {
+            return self.show();
+        }
+        // the image is centered in the view, which is not flipped
+        let (vw, vh) = self.base.size();
+        let (left, bottom) = ((vw as f64 - w as f64) / 2.0, (vh as f64 - h as f64) / 2.0);
+        let origin = NSPoint::new(left + rect.x as f64, bottom + (h as i32 - rect.y - rect.height) as f64);
+        let area = NSRect::new(origin, NSSize::new(rect.width as f64, rect.height as f64));
+        unsafe {
+            let () = msg_send![self.base.control, setNeedsDisplayInRect: area];
+        }
+    }
+    fn invalidate_layout(&mut self) {
+        self.base.invalidate();
+    }
+    fn start_polling(&mut self) {
+        if self.load_timer == nil {
+            unsafe {
+                let interval = load::POLL_INTERVAL as f64 / 1000.0;
+                self.load_timer = msg_send![class!(NSTimer), scheduledTimerWithTimeInterval:interval target:self.base.control selector:sel!(pollLoading:) userInfo:nil repeats:::objc::runtime::YES];
+            }
+        }
+    }
+    fn schedule_frame(&mut self) {
+        self.stop_frame_timer();
+        unsafe {
+            if let Some(delay) = self.state.player.next_delay(Instant::now()) {
+                let interval = animation::millis(delay) as f64 / 1000.0;
+                self.frame_timer = msg_send![class!(NSTimer), scheduledTimerWithTimeInterval:interval target:self.base.control selector:sel!(nextFrame:) userInfo:nil repeats:::objc::runtime::NO];
+            }
+        }
+    }
+}
+
+impl ImageCocoa {
+    fn stop_polling(&mut self) {
+        if self.load_timer != nil {
             unsafe {
-                let () = msg_send![self.base.control, setImageScaling: scale];
+                let () = msg_send![self.load_timer, invalidate];
             }
-            self.base.invalidate();
+            self.load_timer = nil;
         }
     }
-    fn scale(&self) -> super::ScalePolicy {
-        let scale = unsafe { msg_send![self.base.control, imageScaling] };
-        nsscale_to_policy(scale)
+    fn stop_frame_timer(&mut self) {
+        if self.frame_timer != nil {
+            unsafe {
+                let () = msg_send![self.frame_timer, invalidate];
+            }
+            self.frame_timer = nil;
+        }
+    }
+}
+
+impl Drop for ImageCocoa {
+    fn drop(&mut self) {
+        self.stop_polling();
+        self.stop_frame_timer();
     }
 }
 
@@ -134,6 +218,8 @@ impl MemberInner for ImageCocoa {
 
     fn on_set_visibility(&mut self, base: &mut MemberBase) {
         self.base.on_set_visibility(base);
+        self.state.player.set_visible(base.visibility == types::Visibility::Visible);
+        self.schedule_frame();
     }
 
     unsafe fn native_id(&self) -> Self::Id {
@@ -152,33 +238,13 @@ impl Drawable for ImageCocoa {
         self.base.draw(coords);
     }
     fn measure(&mut self, member: &mut MemberBase, control: &mut ControlBase, parent_width: u16, parent_height: u16) -> (u16, u16, bool) {
-        use std::cmp::max;
-
         let old_size = self.base.measured_size;
         self.base.measured_size = match member.visibility {
             types::Visibility::Gone => (0, 0),
-            _ => unsafe {
-                let mut label_size = (0, 0);
-                let w = match control.layout.width {
-                    layout::Size::MatchParent => parent_width as i32,
-                    layout::Size::Exact(w) => w as i32,
-                    layout::Size::WrapContent => {
-                        label_size = common::measure_nsstring(msg_send![self.base.control, title]);
-                        label_size.0 as i32 + DEFAULT_PADDING + DEFAULT_PADDING
-                    }
-                };
-                let h = match control.layout.height {
-                    layout::Size::MatchParent => parent_height as i32,
-                    layout::Size::Exact(h) => h as i32,
-                    layout::Size::WrapContent => {
-                        if label_size.1 < 1 {
-                            label_size = common::measure_nsstring(msg_send![self.base.control, title]);
-                        }
-                        label_size.1 as i32 + DEFAULT_PADDING + DEFAULT_PADDING
-                    }
-                };
-                (max(0, w) as u16, max(0, h) as u16)
-            },
+            _ => {
+                let padding = DEFAULT_PADDING + DEFAULT_PADDING;
+                geometry::measure(self.state.renderer.natural_size(), (&control.layout.width, &control.layout.height), (parent_width, parent_height), (padding, padding), &self.state.constraints)
+            }
         };
         (self.base.measured_size.0, self.base.measured_size.1, self.base.measured_size != old_size)
     }
@@ -187,33 +253,60 @@ impl Drawable for ImageCocoa {
     }
 }
 
-fn policy_to_nsscale(i: super::ScalePolicy) -> u32 {
-    // TODO NSImageScaling
-    match i {
-        super::ScalePolicy::CropCenter => 2,
-        super::ScalePolicy::FitCenter => 3,
-    }
-}
-fn nsscale_to_policy(i: u32) -> super::ScalePolicy {
-    match i {
-        3 => super::ScalePolicy::FitCenter,
-        2 => super::ScalePolicy::CropCenter,
-        _ => {
-            println!("Unknown scale: {}", i);
-            super::ScalePolicy::FitCenter
-        }
-    }
-}
 /*#[allow(dead_code)]
 pub(crate) fn spawn() -> Box<controls::Control> {
     Image::with_label("").into_control()
 }*/
 
+/// Copies the frame into a bitmap the image draws from.
+fn native_image_from_source(frame: super::ImageSource) -> NativeImage {
+    let (w, h) = frame.dimensions();
+    let stride = pixels::BITMAP_REP.stride(w);
+
+    unsafe {
+        // the bitmap allocates its own buffer
+        let bitmap: cocoa_id = msg_send![class!(NSBitmapImageRep), alloc];
+        let bitmap: cocoa_id = msg_send![bitmap, initWithBitmapDataPlanes:ptr::null_mut::<*mut u8>() pixelsWide:w as isize pixelsHigh:h as isize bitsPerSample:8isize samplesPerPixel:4isize hasAlpha:YES isPlanar:NO colorSpaceName:NSDeviceRGBColorSpace bitmapFormat:NS_ALPHA_NONPREMULTIPLIED_BITMAP_FORMAT bytesPerRow:stride as isize bitsPerPixel:32isize];
+        let data: *mut u8 = msg_send![bitmap, bitmapData];
+        pixels::write(frame.pixels(), pixels::BITMAP_REP, slice::from_raw_parts_mut(data, stride * h as usize));
+
+        let image: cocoa_id = msg_send![class!(NSImage), alloc];
+        let image: cocoa_id = msg_send![image, initWithSize:NSSize::new(w as f64, h as f64)];
+        let () = msg_send![image, addRepresentation:bitmap];
+        NativeImage { image: image, bitmap: bitmap }
+    }
+}
+
+/// `NSAlphaNonpremultipliedBitmapFormat`
+const NS_ALPHA_NONPREMULTIPLIED_BITMAP_FORMAT: usize = 1 << 1;
+
+#[link(name = "AppKit", kind = "framework")]
+extern "C" {
+    static NSDeviceRGBColorSpace: cocoa_id;
+}
+
 extern "C" fn set_frame_size(this: &mut Object, _: Sel, param: NSSize) {
     unsafe {
         let sp = common::member_from_cocoa_id_mut::<Image>(this).unwrap();
         let () = msg_send![super(sp.as_inner_mut().as_inner_mut().base.control, Class::get(BASE_CLASS).unwrap()), setFrameSize: param];
+        sp.as_inner_mut().as_inner_mut().show();
         sp.call_on_resize(param.width as u16, param.height as u16)
     }
 }
+extern "C" fn poll_loading(this: &mut Object, _: Sel, _: cocoa_id) {
+    let sp = unsafe { common::member_from_cocoa_id_mut::<Image>(this).unwrap() };
+    let sp2 = unsafe { common::member_from_cocoa_id_mut::<Image>(this).unwrap() };
+    let inner = sp.as_inner_mut().as_inner_mut();
+    if !inner.poll(sp2) {
+        inner.stop_polling();
+    }
+}
+extern "C" fn next_frame(this: &mut Object, _: Sel, _: cocoa_id) {
+    let sp = unsafe { common::member_from_cocoa_id_mut::<Image>(this).unwrap() };
+    let sp2 = unsafe { common::member_from_cocoa_id_mut::<Image>(this).unwrap() };
+    let inner = sp.as_inner_mut().as_inner_mut();
+    // the timer does not repeat, so it is gone already
+    inner.frame_timer = nil;
+    inner.advance(sp2);
+}
 impl_all_defaults!(Image);
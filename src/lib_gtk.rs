@@ -1,10 +1,18 @@
-use super::development as image_dev;
+use super::animation;
+use super::development::{self as image_dev, ImageBackend};
+use super::geometry::{self, Rect};
+use super::load;
+use super::pixels;
+use super::render::State;
 use plygui_gtk::common::*;
 
-use gtk::{Cast, Widget, WidgetExt, Image as GtkImageSys, ImageExt, Bin, BinExt, Label, LabelExt};
-use gdk_pixbuf::{Pixbuf, PixbufExt, Colorspace, InterpType};
-use pango::LayoutExt;
-use cairo::Format;
+use gtk::{Cast, Continue, Widget, WidgetExt, Image as GtkImageSys, ImageExt};
+use glib::SourceId;
+use cairo::{Format, ImageSurface};
+
+use std::os::raw::{c_int, c_void};
+use std::slice;
+use std::time::Instant;
 
 pub type Image = Member<Control<GtkImage>>;
 
@@ -12,83 +20,116 @@ pub type Image = Member<Control<GtkImage>>;
 pub struct GtkImage {
     base: GtkControlBase<Image>,
     
-    scale: super::ScalePolicy,
-    orig: Pixbuf,
+    state: State<ImageSurface>,
+    load_poll: Option<SourceId>,
+    frame_timer: Option<SourceId>,
 }
 
-impl image_dev::ImageInner for GtkImage {
-    fn with_content(content: super::image::DynamicImage) -> Box<super::Image> {
-        use image::GenericImage;
-		
-		let (w, h) = content.dimensions();
-		let raw = content.to_rgba().into_raw();
-		let stride = Format::ARgb32.stride_for_width(w).unwrap();
-		
-		let pixbuf = Pixbuf::new_from_vec(raw, Colorspace::Rgb, true, 8, w as i32, h as i32, stride);        
-        
+impl image_dev::ImageBackend for GtkImage {
+    type Native = ImageSurface;
+
+    fn new(source: Option<super::ImageSource>) -> Box<super::Image> {
         let mut i = Box::new(Member::with_inner(Control::with_inner(GtkImage {
-                base: GtkControlBase::with_gtk_widget(GtkImageSys::new_from_pixbuf(Some(&pixbuf)).upcast::<Widget>()),
-                scale: super::ScalePolicy::FitCenter,  
-                orig: pixbuf,
+                base: GtkControlBase::with_gtk_widget(GtkImageSys::new().upcast::<Widget>()),
+                state: State::new(source),
+                load_poll: None,
+                frame_timer: None,
             }, ()), MemberFunctions::new(_as_any, _as_any_mut, _as_member, _as_member_mut)));
         
         i.as_inner_mut().as_inner_mut().base.widget.connect_size_allocate(on_size_allocate);
         i.as_inner_mut().as_inner_mut().base.widget.connect_show(on_show);
+        i.as_inner_mut().as_inner_mut().base.widget.connect_property_scale_factor_notify(on_scale_factor_notify);
         {
         	let ptr = i.as_ref() as *const _ as *mut ::std::os::raw::c_void;
         	i.as_inner_mut().as_inner_mut().base.set_pointer(ptr);
         }
+        i.as_inner_mut().as_inner_mut().schedule_frame();
         i
     }
-	fn set_scale(&mut self, _: &mut MemberBase, _: &mut ControlBase, policy: super::ScalePolicy) {
-		if self.scale != policy {
-			self.scale = policy;
-			self.base.invalidate();
-		}
-	}
-    fn scale(&self) -> super::ScalePolicy {
-    	self.scale
+    fn state(&self) -> &State<ImageSurface> {
+        &self.state
+    }
+    fn state_mut(&mut self) -> &mut State<ImageSurface> {
+        &mut self.state
+    }
+    fn inner_size(&self) -> (u32, u32) {
+        let (aw, ah) = self.base.measured_size;
+        let (lm, tm, rm, bm) = self.base.margins().into();
+        (cmp::max(1, aw as i32 - lm - rm) as u32, cmp::max(1, ah as i32 - tm - bm) as u32)
+    }
+    fn show(&mut self) {
+        let size = self.frame_size();
+        let scale_factor = self.state.renderer.scale_factor();
+        if let Some(surface) = self.state.frame(size, |frame| surface_from_source(&frame, scale_factor)) {
+            let widget: Widget = self.base.widget.clone().into();
+            widget.downcast::<GtkImageSys>().unwrap().set_from_surface(surface);
+        }
+    }
+    /// Redraws the `rect` part of the surface shown in place, which the image draws from as it is, and has only the part
+    /// of the image showing it drawn again. Shows a new surface if the one shown is of another size.
+    fn redraw(&mut self, rect: Rect) {
+        let (w, h) = self.frame_size();
+        let state = &mut self.state;
+        let (renderer, style) = (&mut state.renderer, &state.style);
+        let redrawn = match state.renditions.reuse((w, h)) {
+            Some(surface) => unsafe {
+                let raw = surface.to_raw_none() as *mut c_void;
+                cairo_surface_flush(raw);
+                let data = slice::from_raw_parts_mut(cairo_image_surface_get_data(raw), pixels::CAIRO.stride(w) * h as usize);
+                pixels::write_rect(&renderer.render_rect(style, w, h, rect), rect, pixels::CAIRO, data, (w, h));
+                cairo_surface_mark_dirty_rectangle(raw, rect.x, rect.y, rect.width, rect.height);
+                true
+            },
+            None => false,
+        };
+        if redrawn {
+            let area = geometry::logical_rect(rect, self.state.renderer.scale_factor());
+            let widget: Widget = self.base.widget.clone().into();
+            widget.queue_draw_area(area.x, area.y, area.width, area.height);
+        } else {
+            self.show();
+        }
+    }
+    fn invalidate_layout(&mut self) {
+        self.base.invalidate();
+    }
+    fn start_polling(&mut self) {
+        if self.load_poll.is_none() {
+            let widget: Widget = self.base.widget.clone().into();
+            self.load_poll = Some(::gtk::timeout_add(load::POLL_INTERVAL, move || on_load_poll(&widget)));
+        }
+    }
+    fn schedule_frame(&mut self) {
+        if let Some(id) = self.frame_timer.take() {
+            ::glib::source_remove(id);
+        }
+        if let Some(delay) = self.state.player.next_delay(Instant::now()) {
+            let widget: Widget = self.base.widget.clone().into();
+            self.frame_timer = Some(::gtk::timeout_add(animation::millis(delay), move || on_frame_timer(&widget)));
+        }
     }
 }
 
 impl GtkImage {
-    fn apply_sized_image(&mut self) {
-        let bm_width = self.orig.get_width();
-    	let bm_height = self.orig.get_height();
-    	
-    	let (aw, ah) = self.base.measured_size;
-    	let (lm, tm, rm, bm) = self.base.margins().into();
-        let hoffs = lm;
-        let voffs = tm;
-        let hdiff = hoffs + rm;
-        let vdiff = voffs + bm;
-        let inner_h = aw as i32 - hdiff;
-    	let inner_v = ah as i32 - vdiff;
-    
-    	let (wrate, hrate) = (inner_h as f32 / bm_width as f32, inner_v as f32 / bm_height as f32);
-        let less_rate = fmin(wrate, hrate);
-        
-        println!("{}, {}, {}, {}, {}", aw, ah, hoffs, voffs, less_rate);
-        
-        let scaled = match self.scale {
-    		super::ScalePolicy::FitCenter => {
-        		let bm_h = (bm_width as f32 * less_rate) as i32;
-            	let bm_v = (bm_height as f32 * less_rate) as i32;
-        		let alpha = self.orig.get_has_alpha();
-            	let bits = self.orig.get_bits_per_sample();
-    	
-        		let scaled = Pixbuf::new(Colorspace::Rgb, alpha, bits, bm_h, bm_v);
-            	self.orig.scale(&scaled, 0, 0, bm_h, bm_v, 0f64, 0f64, less_rate as f64, less_rate as f64, InterpType::Hyper);
-                scaled
-    		},
-    		super::ScalePolicy::CropCenter => {
-    			let half_diff_h = (bm_width - aw as i32) / 2;
-    			let half_diff_v = (bm_height - ah as i32) / 2;
-        		self.orig.new_subpixbuf(cmp::max(0, half_diff_h), cmp::max(0, half_diff_v), cmp::min(bm_width, inner_h), cmp::min(bm_height, inner_v)).unwrap()
-    		}
-    	};
-    	let image: Widget = self.base.widget.clone().into();
-    	image.downcast::<GtkImageSys>().unwrap().set_from_pixbuf(&scaled);
+    /// Renders for the scale factor of the screen the image is on, if it is not the one rendered for so far.
+    fn update_scale_factor(&mut self) {
+        let widget: Widget = self.base.widget.clone().into();
+        let scale_factor = widget.get_scale_factor() as f32;
+        if self.state.renderer.scale_factor() != scale_factor {
+            self.state.renderer.set_scale_factor(scale_factor);
+            self.restyle();
+        }
+    }
+}
+
+impl Drop for GtkImage {
+    fn drop(&mut self) {
+        if let Some(id) = self.load_poll.take() {
+            ::glib::source_remove(id);
+        }
+        if let Some(id) = self.frame_timer.take() {
+            ::glib::source_remove(id);
+        }
     }
 }
 
@@ -143,7 +184,9 @@ impl MemberInner for GtkImage {
     	self.base.measured_size
     }
     
-    fn on_set_visibility(&mut self, _: &mut MemberBase) {
+    fn on_set_visibility(&mut self, member: &mut MemberBase) {
+        self.state.player.set_visible(member.visibility == types::Visibility::Visible);
+        self.schedule_frame();
     	self.base.invalidate()
     }
     
@@ -161,36 +204,8 @@ impl Drawable for GtkImage {
     	self.base.measured_size = match member.visibility {
             types::Visibility::Gone => (0, 0),
             _ => {
-                let (lm,tm,rm,bm) = self.base.margins().into();
-		    	    	
-		    	let mut label_size = (-1i32, -1i32);
-                let w = match control.layout.width {
-                    layout::Size::MatchParent => parent_width as i32,
-                    layout::Size::Exact(w) => w as i32,
-                    layout::Size::WrapContent => {
-                        if label_size.0 < 0 {
-                        	let self_widget: Widget = self.base.widget.clone().into();
-                        	let mut bin = self_widget.downcast::<Bin>().unwrap();
-                        	let mut label = bin.get_child().unwrap().downcast::<Label>().unwrap();		
-                        	label_size = label.get_layout().unwrap().get_pixel_size();			
-                        }
-                        label_size.0 + lm + rm
-                    } 
-                };
-                let h = match control.layout.height {
-                    layout::Size::MatchParent => parent_height as i32,
-                    layout::Size::Exact(h) => h as i32,
-                    layout::Size::WrapContent => {
-                        if label_size.1 < 0 {
-                        	let self_widget: Widget = self.base.widget.clone().into();
-                            let mut bin = self_widget.downcast::<Bin>().unwrap();
-                        	let mut label = bin.get_child().unwrap().downcast::<Label>().unwrap();		
-                        	label_size = label.get_layout().unwrap().get_pixel_size();	
-                        }
-                        label_size.1 + tm + bm
-                    } 
-                };
-                (cmp::max(0, w) as u16, cmp::max(0, h) as u16)
+                let (lm, tm, rm, bm) = self.base.margins().into();
+                geometry::measure(self.state.renderer.natural_size(), (&control.layout.width, &control.layout.height), (parent_width, parent_height), (lm + rm, tm + bm), &self.state.constraints)
             },
         };
     	(
@@ -212,7 +227,15 @@ pub(crate) fn spawn() -> Box<controls::Control> {
 fn on_show(this: &::gtk::Widget) {
     let mut ll1 = this.clone().upcast::<Widget>();
     let ll1 = cast_gtk_widget_to_member_mut::<Image>(&mut ll1).unwrap();
-	ll1.as_inner_mut().as_inner_mut().apply_sized_image();
+    ll1.as_inner_mut().as_inner_mut().update_scale_factor();
+	ll1.as_inner_mut().as_inner_mut().show();
+}
+
+fn on_scale_factor_notify(this: &::gtk::Widget) {
+    let mut ll1 = this.clone().upcast::<Widget>();
+    if let Some(ll1) = cast_gtk_widget_to_member_mut::<Image>(&mut ll1) {
+        ll1.as_inner_mut().as_inner_mut().update_scale_factor();
+    }
 }
 
 fn on_size_allocate(this: &::gtk::Widget, _allo: &::gtk::Rectangle) {
@@ -221,7 +244,7 @@ fn on_size_allocate(this: &::gtk::Widget, _allo: &::gtk::Rectangle) {
 	let ll1 = cast_gtk_widget_to_member_mut::<Image>(&mut ll1).unwrap();
 	let ll2 = cast_gtk_widget_to_member_mut::<Image>(&mut ll2).unwrap();
 	
-	ll1.as_inner_mut().as_inner_mut().apply_sized_image();
+	ll1.as_inner_mut().as_inner_mut().show();
 	
 	let measured_size = ll1.as_inner().as_inner().base.measured_size;
 	if let Some(ref mut cb) = ll1.base_mut().handler_resize {
@@ -229,8 +252,61 @@ fn on_size_allocate(this: &::gtk::Widget, _allo: &::gtk::Rectangle) {
     }
 }
 
-fn fmin(a: f32, b: f32) -> f32 {
-	if a < b { a } else { b }
+fn on_load_poll(this: &::gtk::Widget) -> Continue {
+    let mut ll1 = this.clone().upcast::<Widget>();
+    let mut ll2 = this.clone().upcast::<Widget>();
+    let ll1 = match cast_gtk_widget_to_member_mut::<Image>(&mut ll1) {
+        Some(ll1) => ll1,
+        None => return Continue(false),
+    };
+    let ll2 = cast_gtk_widget_to_member_mut::<Image>(&mut ll2).unwrap();
+    
+    let inner = ll1.as_inner_mut().as_inner_mut();
+    if inner.poll(ll2) {
+        Continue(true)
+    } else {
+        inner.load_poll = None;
+        Continue(false)
+    }
+}
+
+fn on_frame_timer(this: &::gtk::Widget) -> Continue {
+    let mut ll1 = this.clone().upcast::<Widget>();
+    let mut ll2 = this.clone().upcast::<Widget>();
+    let ll1 = match cast_gtk_widget_to_member_mut::<Image>(&mut ll1) {
+        Some(ll1) => ll1,
+        None => return Continue(false),
+    };
+    let ll2 = cast_gtk_widget_to_member_mut::<Image>(&mut ll2).unwrap();
+
+    let inner = ll1.as_inner_mut().as_inner_mut();
+    // the source is removed by returning false, a new one is added for the next frame
+    inner.frame_timer = None;
+    inner.advance(ll2);
+    Continue(false)
+}
+
+/// Copies the frame into a surface of the scale of the screen, so GTK does not upscale the device pixels again.
+fn surface_from_source(frame: &super::ImageSource, scale_factor: f32) -> ImageSurface {
+    let (w, h) = frame.dimensions();
+    let mut surface = ImageSurface::create(Format::ARgb32, w as i32, h as i32).expect("could not create an image surface");
+    debug_assert_eq!(surface.get_stride() as usize, pixels::CAIRO.stride(w));
+    if let Ok(mut data) = surface.get_data() {
+        pixels::write(frame.pixels(), pixels::CAIRO, &mut data);
+    }
+    unsafe {
+        cairo_surface_set_device_scale(surface.to_raw_none() as *mut c_void, scale_factor as f64, scale_factor as f64);
+    }
+    surface
+}
+
+// `ImageSurface::get_data` refuses a surface the image holds a reference to as well,
+// the surface shown is written to through its raw data instead
+extern "C" {
+    fn cairo_surface_flush(surface: *mut c_void);
+    fn cairo_surface_mark_dirty_rectangle(surface: *mut c_void, x: c_int, y: c_int, width: c_int, height: c_int);
+    fn cairo_surface_set_device_scale(surface: *mut c_void, x_scale: f64, y_scale: f64);
+    fn cairo_image_surface_get_data(surface: *mut c_void) -> *mut u8;
 }
 
 impl_all_defaults!(Image);
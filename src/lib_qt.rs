@@ -1,33 +1,46 @@
-use super::development as image_dev;
+use super::animation;
+use super::development::{self as image_dev, ImageBackend};
+use super::geometry::{self, Rect};
+use super::load;
+use super::pixels;
+use super::render::State;
 
 use plygui_qt::common::*;
 
-use qt_core::qt::{AlignmentFlag, AspectRatioMode};
-use qt_core::rect::{Rect as QRect};
+use qt_core::cpp_utils::UnsafeStaticCast;
+use qt_core::qt::AlignmentFlag;
+use qt_core::rect::Rect as QRect;
+use qt_core::timer_event::TimerEvent as QTimerEvent;
 use qt_gui::image::{Format, Image as QImage};
+use qt_gui::paint_device::PaintDevice as QPaintDevice;
+use qt_gui::painter::{CompositionMode, Painter as QPainter};
 use qt_gui::pixmap::Pixmap as QPixmap;
 use qt_widgets::label::Label as QLabel;
 
+use std::time::Instant;
+
 pub type Image = Member<Control<QtImage>>;
 
 #[repr(C)]
 pub struct QtImage {
     base: QtControlBase<Image, QLabel>,
 
-    scale: super::ScalePolicy,
-    pixmap: CppBox<QPixmap>,
-    content: super::image::DynamicImage,
+    state: State<CppBox<QPixmap>>,
+    load_timer: i32,
+    frame_timer: i32,
 }
 
-impl image_dev::ImageInner for QtImage {
-    fn with_content(content: super::image::DynamicImage) -> Box<super::Image> {
+impl image_dev::ImageBackend for QtImage {
+    type Native = CppBox<QPixmap>;
+
+    fn new(source: Option<super::ImageSource>) -> Box<super::Image> {
         let mut i = Box::new(Member::with_inner(
             Control::with_inner(
                 QtImage {
                     base: QtControlBase::with_params(QLabel::new(()), event_handler),
-                    scale: super::ScalePolicy::FitCenter,
-                    pixmap: unsafe { CppBox::new(ptr::null_mut()) },
-                    content: content,
+                    state: State::new(source),
+                    load_timer: 0,
+                    frame_timer: 0,
                 },
                 (),
             ),
@@ -40,38 +53,92 @@ impl image_dev::ImageInner for QtImage {
             let qo: &mut QObject = i.as_inner_mut().as_inner_mut().base.widget.static_cast_mut();
             qo.set_property(PROPERTY.as_ptr() as *const i8, &QVariant::new0(ptr));
         }
-        i.as_inner_mut().as_inner_mut().base.widget.set_alignment(Flags::from_enum(AlignmentFlag::Center));
-        i.as_inner_mut().as_inner_mut().update_image();
+        let scale_factor = i.as_inner().as_inner().device_pixel_ratio();
+        i.as_inner_mut().as_inner_mut().state.renderer.set_scale_factor(scale_factor);
+        i.as_inner_mut().as_inner_mut().show();
+        i.as_inner_mut().as_inner_mut().schedule_frame();
         i
     }
-    fn set_scale(&mut self, _: &mut MemberBase, _: &mut ControlBase, policy: super::ScalePolicy) {
-        if self.scale != policy {
-            self.scale = policy;
-            self.base.invalidate();
+    fn state(&self) -> &State<CppBox<QPixmap>> {
+        &self.state
+    }
+    fn state_mut(&mut self) -> &mut State<CppBox<QPixmap>> {
+        &mut self.state
+    }
+    fn inner_size(&self) -> (u32, u32) {
+        let margins = self.base.widget.contents_margins();
+        let (aw, ah) = self.size();
+        (cmp::max(1, aw as i32 - margins.left() - margins.right()) as u32, cmp::max(1, ah as i32 - margins.top() - margins.bottom()) as u32)
+    }
+    fn show(&mut self) {
+        self.base.widget.set_alignment(gravity_to_alignment(self.state.style.gravity));
+        let size = self.frame_size();
+        let scale_factor = self.state.renderer.scale_factor();
+        if let Some(pixmap) = self.state.frame(size, |frame| pixmap_from_source(&frame, scale_factor)) {
+            // the label keeps its own implicitly shared copy
+            self.base.widget.set_pixmap(pixmap.as_ref());
         }
     }
-    fn scale(&self) -> super::ScalePolicy {
-        self.scale
+    /// Redraws the `rect` part of the pixmap shown, hands it to the label again and has only the part of the label
+    /// showing it repainted. Shows a new pixmap if the one shown is of another size.
+    fn redraw(&mut self, rect: Rect) {
+        let (w, h) = self.frame_size();
+        let state = &mut self.state;
+        let (renderer, style) = (&mut state.renderer, &state.style);
+        let scale_factor = renderer.scale_factor();
+        match state.renditions.reuse((w, h)) {
+            Some(pixmap) => {
+                let part = renderer.render_rect(style, w, h, rect);
+                debug_assert!(pixels::QIMAGE.is_rgba(rect.width as u32));
+                let img = unsafe { QImage::new_unsafe((part.as_ptr(), rect.width, rect.height, rect.width * 4, Format::FormatRGBA8888)) };
+                // only the pixmap kept here is painted, the label gets a new implicitly shared copy of it
+                paint_rect(pixmap, &img, rect, scale_factor);
+                self.base.widget.set_pixmap(pixmap.as_ref());
+            }
+            None => return self.restyle(),
+        }
+        // the pixmap fills the contents of the label
+        let margins = self.base.widget.contents_margins();
+        let area = geometry::logical_rect(rect, scale_factor);
+        let area = QRect::new((margins.left() + area.x, margins.top() + area.y, area.width, area.height));
+        self.base.widget.update(area.as_ref());
+    }
+    fn invalidate_layout(&mut self) {
+        self.base.invalidate();
+    }
+    fn start_polling(&mut self) {
+        if self.load_timer == 0 {
+            use qt_core::cpp_utils::StaticCast;
+            let qo: &mut QObject = self.base.widget.static_cast_mut();
+            self.load_timer = qo.start_timer(load::POLL_INTERVAL as i32);
+        }
+    }
+    fn schedule_frame(&mut self) {
+        use qt_core::cpp_utils::StaticCast;
+        let qo: &mut QObject = self.base.widget.static_cast_mut();
+        if self.frame_timer != 0 {
+            qo.kill_timer(self.frame_timer);
+            self.frame_timer = 0;
+        }
+        if let Some(delay) = self.state.player.next_delay(Instant::now()) {
+            self.frame_timer = qo.start_timer(animation::millis(delay) as i32);
+        }
     }
 }
 
 impl QtImage {
-    fn update_image(&mut self) {
-        use image::GenericImage;
-
-        let (iw, ih) = self.size();
-        let (w, h) = self.content.dimensions();
-        let raw = self.content.to_rgba().into_raw();
-        let img = unsafe { QImage::new_unsafe((raw.as_ptr(), w as i32, h as i32, Format::FormatRGBA8888)) };
-        self.pixmap = match self.scale {
-            super::ScalePolicy::FitCenter => {
-                QPixmap::from_image(img.as_ref()).scaled((iw as i32, ih as i32, AspectRatioMode::KeepAspectRatio))
-            },
-            super::ScalePolicy::CropCenter => {
-                QPixmap::from_image(img.as_ref()).copy(&QRect::new(((w as i32 - iw as i32)/2, (h as i32 - ih as i32)/2, iw as i32, ih as i32)))
-            }
-        };
-        self.base.widget.set_pixmap(self.pixmap.as_ref());
+    fn device_pixel_ratio(&self) -> f32 {
+        use qt_core::cpp_utils::StaticCast;
+        let device: &QPaintDevice = self.base.widget.static_cast();
+        device.device_pixel_ratio_f() as f32
+    }
+    /// Renders for the pixel ratio of the screen the image is on, if it is not the one rendered for so far.
+    fn update_scale_factor(&mut self) {
+        let scale_factor = self.device_pixel_ratio();
+        if self.state.renderer.scale_factor() != scale_factor {
+            self.state.renderer.set_scale_factor(scale_factor);
+            self.restyle();
+        }
     }
 }
 
@@ -116,6 +183,8 @@ impl MemberInner for QtImage {
 
     fn on_set_visibility(&mut self, base: &mut MemberBase) {
         self.base.set_visibility(base.visibility);
+        self.state.player.set_visible(base.visibility == types::Visibility::Visible);
+        self.schedule_frame();
         self.base.invalidate()
     }
     fn size(&self) -> (u16, u16) {
@@ -136,22 +205,8 @@ impl Drawable for QtImage {
             types::Visibility::Gone => (0, 0),
             _ => {
                 let margins = self.base.widget.contents_margins();
-                let size = if self.pixmap.is_null() {
-                    QSize::new((0, 0))
-                } else {
-                    self.pixmap.size()
-                };
-                let w = match control.layout.width {
-                    layout::Size::MatchParent => parent_width as i32,
-                    layout::Size::Exact(w) => w as i32,
-                    layout::Size::WrapContent => size.width() as i32 + margins.left() + margins.right(),
-                };
-                let h = match control.layout.height {
-                    layout::Size::MatchParent => parent_height as i32,
-                    layout::Size::Exact(h) => h as i32,
-                    layout::Size::WrapContent => size.height() as i32 + margins.top() + margins.bottom(),
-                };
-                (cmp::max(0, w) as u16, cmp::max(0, h) as u16)
+                let (hm, vm) = (margins.left() + margins.right(), margins.top() + margins.bottom());
+                geometry::measure(self.state.renderer.natural_size(), (&control.layout.width, &control.layout.height), (parent_width, parent_height), (hm, vm), &self.state.constraints)
             }
         };
         (self.base.measured_size.0, self.base.measured_size.1, self.base.measured_size != old_size)
@@ -166,13 +221,55 @@ pub(crate) fn spawn() -> Box<controls::Control> {
 	Image::with_content("").into_control()
 }*/
 
+fn gravity_to_alignment(gravity: super::Gravity) -> Flags<AlignmentFlag> {
+    let (h, v) = match gravity {
+        super::Gravity::TopLeft => (AlignmentFlag::AlignLeft, AlignmentFlag::AlignTop),
+        super::Gravity::Top => (AlignmentFlag::AlignHCenter, AlignmentFlag::AlignTop),
+        super::Gravity::TopRight => (AlignmentFlag::AlignRight, AlignmentFlag::AlignTop),
+        super::Gravity::Left => (AlignmentFlag::AlignLeft, AlignmentFlag::AlignVCenter),
+        super::Gravity::Center => (AlignmentFlag::AlignHCenter, AlignmentFlag::AlignVCenter),
+        super::Gravity::Right => (AlignmentFlag::AlignRight, AlignmentFlag::AlignVCenter),
+        super::Gravity::BottomLeft => (AlignmentFlag::AlignLeft, AlignmentFlag::AlignBottom),
+        super::Gravity::Bottom => (AlignmentFlag::AlignHCenter, AlignmentFlag::AlignBottom),
+        super::Gravity::BottomRight => (AlignmentFlag::AlignRight, AlignmentFlag::AlignBottom),
+    };
+    Flags::from_enum(h) | v
+}
+
+/// Paints `image` over the `rect` part of `pixmap`, both in device pixels.
+fn paint_rect(pixmap: &mut QPixmap, image: &QImage, rect: Rect, scale_factor: f32) {
+    use qt_core::cpp_utils::StaticCast;
+
+    // painted in device pixels, not scaled by the painter
+    pixmap.set_device_pixel_ratio(1.0);
+    {
+        let device: &mut QPaintDevice = pixmap.static_cast_mut();
+        let mut painter = unsafe { QPainter::new_unsafe(device as *mut QPaintDevice) };
+        painter.set_composition_mode(CompositionMode::Source);
+        painter.draw_image((rect.x, rect.y, image));
+    }
+    pixmap.set_device_pixel_ratio(scale_factor as f64);
+}
+
+/// The image only borrows the frame buffer, the pixmap is the one copy made.
+fn pixmap_from_source(content: &super::ImageSource, scale_factor: f32) -> CppBox<QPixmap> {
+    let (w, h) = content.dimensions();
+    let bytes = pixels::as_layout(content, pixels::QIMAGE);
+    let img = unsafe { QImage::new_unsafe((bytes.as_ptr(), w as i32, h as i32, Format::FormatRGBA8888)) };
+    let mut pixmap = QPixmap::from_image(img.as_ref());
+    // the frame is rendered in device pixels, the label lays it out in logical ones
+    pixmap.set_device_pixel_ratio(scale_factor as f64);
+    pixmap
+}
+
 fn event_handler(object: &mut QObject, event: &QEvent) -> bool {
     match event.type_() {
         QEventType::Resize => {
             let ptr = unsafe { object.property(PROPERTY.as_ptr() as *const i8).to_u_long_long() };
             if ptr != 0 {
                 let sc: &mut Image = unsafe { mem::transmute(ptr) };
-                sc.as_inner_mut().as_inner_mut().update_image();
+                sc.as_inner_mut().as_inner_mut().update_scale_factor();
+                sc.as_inner_mut().as_inner_mut().show();
                 if sc.as_inner().as_inner().base.dirty {
                     sc.as_inner_mut().as_inner_mut().base.dirty = false;
                     let (width, height) = sc.as_inner().as_inner().size();
@@ -180,6 +277,30 @@ fn event_handler(object: &mut QObject, event: &QEvent) -> bool {
                 }
             }
         },
+        QEventType::ScreenChangeInternal => {
+            let ptr = unsafe { object.property(PROPERTY.as_ptr() as *const i8).to_u_long_long() };
+            if ptr != 0 {
+                let sc: &mut Image = unsafe { mem::transmute(ptr) };
+                sc.as_inner_mut().as_inner_mut().update_scale_factor();
+            }
+        },
+        QEventType::Timer => {
+            let ptr = unsafe { object.property(PROPERTY.as_ptr() as *const i8).to_u_long_long() };
+            if ptr != 0 {
+                let sc: &mut Image = unsafe { mem::transmute(ptr) };
+                let sc2: &mut Image = unsafe { mem::transmute(ptr) };
+                let inner = sc.as_inner_mut().as_inner_mut();
+                let timer_id = unsafe { UnsafeStaticCast::<QTimerEvent>::static_cast(event) }.timer_id();
+                if timer_id == inner.load_timer {
+                    if !inner.poll(sc2) {
+                        object.kill_timer(inner.load_timer);
+                        inner.load_timer = 0;
+                    }
+                } else if timer_id == inner.frame_timer {
+                    inner.advance(sc2);
+                }
+            }
+        },
         QEventType::Destroy => {
             if let Some(ll) = cast_qobject_to_uimember_mut::<Image>(object) {
                 unsafe { ptr::write(&mut ll.as_inner_mut().as_inner_mut().base.widget, CppBox::new(ptr::null_mut())); }
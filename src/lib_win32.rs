@@ -1,96 +1,129 @@
-use super::development as image_dev;
+use super::animation;
+use super::development::{self as image_dev, ImageBackend};
+use super::geometry::{self, Rect};
+use super::load;
+use super::pixels;
+use super::render::State;
 
 use plygui_win32::common::*;
 
+use image::RgbaImage;
+
+use std::time::Instant;
+
 lazy_static! {
     pub static ref WINDOW_CLASS: Vec<u16> = OsStr::new("STATIC").encode_wide().chain(Some(0).into_iter()).collect::<Vec<_>>();
 }
 
+const LOAD_TIMER_ID: usize = 1;
+const FRAME_TIMER_ID: usize = 2;
+
 pub type Image = Member<Control<ImageWin32>>;
 
 #[repr(C)]
 pub struct ImageWin32 {
     base: WindowsControlBase<Image>,
 
-    bmp: windef::HBITMAP,
-    scale: super::ScalePolicy,
+    state: State<Bitmap>,
 }
 
-impl ImageWin32 {
-    fn install_image(&mut self, content: super::image::DynamicImage) {
-        use image::GenericImage;
-
-        let (w, h) = content.dimensions();
-
-        let bminfo = wingdi::BITMAPINFO {
-            bmiHeader: wingdi::BITMAPINFOHEADER {
-                biSize: mem::size_of::<wingdi::BITMAPINFOHEADER>() as u32,
-                biWidth: w as i32,
-                biHeight: h as i32,
-                biPlanes: 1,
-                biBitCount: 32,
-                biCompression: wingdi::BI_RGB,
-                biSizeImage: 0,
-                biXPelsPerMeter: 0,
-                biYPelsPerMeter: 0,
-                biClrUsed: 0,
-                biClrImportant: 0,
-            },
-            bmiColors: unsafe { mem::zeroed() },
-        };
+/// DIB section holding a rendered frame and its pixel bits, deleted when dropped.
+pub struct Bitmap(windef::HBITMAP, *mut u8);
 
-        unsafe {
-            let mut pv_image_bits = ptr::null_mut();
-            let hdc_screen = winuser::GetDC(ptr::null_mut());
-            self.bmp = wingdi::CreateDIBSection(hdc_screen, &bminfo, wingdi::DIB_RGB_COLORS, &mut pv_image_bits, ptr::null_mut(), 0);
-            winuser::ReleaseDC(ptr::null_mut(), hdc_screen);
-            if self.bmp.is_null() {
-                panic!("Could not load image.")
+impl Drop for Bitmap {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe {
+                wingdi::DeleteObject(self.0 as *mut c_void);
             }
-
-            ptr::copy(content.flipv().to_rgba().into_raw().as_ptr(), pv_image_bits as *mut u8, (w * h * 4) as usize);
-        }
-    }
-    fn remove_image(&mut self) {
-        unsafe {
-            wingdi::DeleteObject(self.bmp as *mut c_void);
         }
-        self.bmp = ptr::null_mut();
     }
 }
 
-impl Drop for ImageWin32 {
-    fn drop(&mut self) {
-        self.remove_image();
-    }
-}
+impl image_dev::ImageBackend for ImageWin32 {
+    type Native = Bitmap;
 
-impl image_dev::ImageInner for ImageWin32 {
-    fn with_content(content: super::image::DynamicImage) -> Box<super::Image> {
-        let mut i = Box::new(Member::with_inner(
+    fn new(source: Option<super::ImageSource>) -> Box<super::Image> {
+        Box::new(Member::with_inner(
             Control::with_inner(
                 ImageWin32 {
                     base: WindowsControlBase::new(),
-
-                    bmp: ptr::null_mut(),
-                    scale: super::ScalePolicy::FitCenter,
+                    state: State::new(source),
                 },
                 (),
             ),
             MemberFunctions::new(_as_any, _as_any_mut, _as_member, _as_member_mut),
-        ));
-
-        i.as_inner_mut().as_inner_mut().install_image(content);
-        i
+        ))
     }
-    fn set_scale(&mut self, _member: &mut MemberBase, _control: &mut ControlBase, policy: super::ScalePolicy) {
-        if self.scale != policy {
-            self.scale = policy;
-            self.base.invalidate();
+    fn state(&self) -> &State<Bitmap> {
+        &self.state
+    }
+    fn state_mut(&mut self) -> &mut State<Bitmap> {
+        &mut self.state
+    }
+    fn inner_size(&self) -> (u32, u32) {
+        let (w, h) = self.size();
+        (cmp::max(1, w as i32 - DEFAULT_PADDING - DEFAULT_PADDING) as u32, cmp::max(1, h as i32 - DEFAULT_PADDING - DEFAULT_PADDING) as u32)
+    }
+    /// Repaints everything, the frame of the current size is rendered on the paint.
+    fn show(&mut self) {
+        if !self.base.hwnd.is_null() {
+            unsafe {
+                winuser::InvalidateRect(self.base.hwnd, ptr::null_mut(), minwindef::TRUE);
+            }
+        }
+    }
+    /// Redraws the `rect` part of the bitmap shown in place and repaints just that part,
+    /// or repaints everything if the bitmap shown is of another size.
+    fn redraw(&mut self, rect: Rect) {
+        let (w, h) = self.frame_size();
+        let state = &mut self.state;
+        let (renderer, style) = (&mut state.renderer, &state.style);
+        let redrawn = match state.renditions.reuse((w, h)) {
+            Some(bitmap) if !bitmap.1.is_null() => unsafe {
+                // GDI may still be drawing from the bits
+                wingdi::GdiFlush();
+                let bits = ::std::slice::from_raw_parts_mut(bitmap.1, pixels::DIB.stride(w) * h as usize);
+                pixels::write_rect(&renderer.render_rect(style, w, h, rect), rect, pixels::DIB, bits, (w, h));
+                true
+            },
+            _ => false,
+        };
+        if self.base.hwnd.is_null() {
+            return;
+        }
+        let area = windef::RECT {
+            left: DEFAULT_PADDING + rect.x,
+            top: DEFAULT_PADDING + rect.y,
+            right: DEFAULT_PADDING + rect.x + rect.width,
+            bottom: DEFAULT_PADDING + rect.y + rect.height,
+        };
+        unsafe {
+            winuser::InvalidateRect(self.base.hwnd, if redrawn { &area } else { ptr::null() }, minwindef::TRUE);
         }
     }
-    fn scale(&self) -> super::ScalePolicy {
-        self.scale
+    fn invalidate_layout(&mut self) {
+        self.base.invalidate();
+    }
+    /// Checks on a timer which lives as long as the own window does, so it starts once the window is created too.
+    fn start_polling(&mut self) {
+        if (self.state.loader.is_loading() || self.state.sink.is_some()) && !self.base.hwnd.is_null() {
+            unsafe {
+                winuser::SetTimer(self.base.hwnd, LOAD_TIMER_ID, load::POLL_INTERVAL, None);
+            }
+        }
+    }
+    /// Times the next frame with a timer, which lives as long as the own window does.
+    fn schedule_frame(&mut self) {
+        if self.base.hwnd.is_null() {
+            return;
+        }
+        unsafe {
+            winuser::KillTimer(self.base.hwnd, FRAME_TIMER_ID);
+            if let Some(delay) = self.state.player.next_delay(Instant::now()) {
+                winuser::SetTimer(self.base.hwnd, FRAME_TIMER_ID, animation::millis(delay), None);
+            }
+        }
     }
 }
 
@@ -116,6 +149,8 @@ impl ControlInner for ImageWin32 {
         };
         self.base.hwnd = hwnd;
         self.base.subclass_id = id;
+        self.start_polling();
+        self.schedule_frame();
     }
     fn on_removed_from_container(&mut self, _member: &mut MemberBase, _control: &mut ControlBase, _: &controls::Container) {
         destroy_hwnd(self.base.hwnd, self.base.subclass_id, Some(handler));
@@ -156,6 +191,8 @@ impl MemberInner for ImageWin32 {
         self.base.size()
     }
     fn on_set_visibility(&mut self, base: &mut MemberBase) {
+        self.state.player.set_visible(base.visibility == types::Visibility::Visible);
+        self.schedule_frame();
         let hwnd = self.base.hwnd;
         if !hwnd.is_null() {
             unsafe {
@@ -173,26 +210,13 @@ impl Drawable for ImageWin32 {
     fn draw(&mut self, _member: &mut MemberBase, _control: &mut ControlBase, coords: Option<(i32, i32)>) {
         self.base.draw(coords);
     }
-    fn measure(&mut self, member: &mut MemberBase, control: &mut ControlBase, w: u16, h: u16) -> (u16, u16, bool) {
+    fn measure(&mut self, member: &mut MemberBase, control: &mut ControlBase, parent_width: u16, parent_height: u16) -> (u16, u16, bool) {
         let old_size = self.base.measured_size;
         self.base.measured_size = match member.visibility {
             types::Visibility::Gone => (0, 0),
             _ => {
-                let w = match control.layout.width {
-                    layout::Size::MatchParent => w,
-                    layout::Size::Exact(w) => w,
-                    layout::Size::WrapContent => {
-                        42 as u16 // TODO min_width
-                    }
-                };
-                let h = match control.layout.height {
-                    layout::Size::MatchParent => h,
-                    layout::Size::Exact(h) => h,
-                    layout::Size::WrapContent => {
-                        42 as u16 // TODO min_height
-                    }
-                };
-                (cmp::max(0, w as i32) as u16, cmp::max(0, h as i32) as u16)
+                let padding = DEFAULT_PADDING + DEFAULT_PADDING;
+                geometry::measure(self.state.renderer.natural_size(), (&control.layout.width, &control.layout.height), (parent_width, parent_height), (padding, padding), &self.state.constraints)
             }
         };
         (self.base.measured_size.0, self.base.measured_size.1, self.base.measured_size != old_size)
@@ -211,6 +235,43 @@ pub(crate) fn spawn() -> Box<controls::Control> {
 }
 */
 
+/// Copies `frame` into a new DIB section. The bitmap is null if it could not be created, e.g. being out of GDI resources.
+fn bitmap_from_rgba(frame: &RgbaImage) -> Bitmap {
+    let (w, h) = frame.dimensions();
+
+    let bminfo = wingdi::BITMAPINFO {
+        bmiHeader: wingdi::BITMAPINFOHEADER {
+            biSize: mem::size_of::<wingdi::BITMAPINFOHEADER>() as u32,
+            biWidth: w as i32,
+            biHeight: h as i32,
+            biPlanes: 1,
+            biBitCount: 32,
+            biCompression: wingdi::BI_RGB,
+            biSizeImage: 0,
+            biXPelsPerMeter: 0,
+            biYPelsPerMeter: 0,
+            biClrUsed: 0,
+            biClrImportant: 0,
+        },
+        bmiColors: unsafe { mem::zeroed() },
+    };
+
+    unsafe {
+        let mut pv_image_bits = ptr::null_mut();
+        let hdc_screen = winuser::GetDC(ptr::null_mut());
+        let handle = wingdi::CreateDIBSection(hdc_screen, &bminfo, wingdi::DIB_RGB_COLORS, &mut pv_image_bits, ptr::null_mut(), 0);
+        winuser::ReleaseDC(ptr::null_mut(), hdc_screen);
+        let bitmap = Bitmap(handle, pv_image_bits as *mut u8);
+        if bitmap.0.is_null() || pv_image_bits.is_null() {
+            return Bitmap(ptr::null_mut(), ptr::null_mut());
+        }
+
+        let bits = ::std::slice::from_raw_parts_mut(bitmap.1, pixels::DIB.stride(w) * h as usize);
+        pixels::write(frame, pixels::DIB, bits);
+        bitmap
+    }
+}
+
 unsafe extern "system" fn handler(hwnd: windef::HWND, msg: minwindef::UINT, wparam: minwindef::WPARAM, lparam: minwindef::LPARAM, _: usize, param: usize) -> isize {
     let sc: &mut Image = mem::transmute(param);
     let ww = winuser::GetWindowLongPtrW(hwnd, winuser::GWLP_USERDATA);
@@ -224,61 +285,46 @@ unsafe extern "system" fn handler(hwnd: windef::HWND, msg: minwindef::UINT, wpar
 
             sc.call_on_resize(width, height);
         }
+        winuser::WM_TIMER if wparam == LOAD_TIMER_ID => {
+            let sc2: &mut Image = mem::transmute(param);
+            if !sc.as_inner_mut().as_inner_mut().poll(sc2) {
+                winuser::KillTimer(hwnd, LOAD_TIMER_ID);
+            }
+            return 0;
+        }
+        winuser::WM_TIMER if wparam == FRAME_TIMER_ID => {
+            let sc2: &mut Image = mem::transmute(param);
+            sc.as_inner_mut().as_inner_mut().advance(sc2);
+            return 0;
+        }
         winuser::WM_PAINT => {
             let sc = sc.as_inner_mut().as_inner_mut();
-            let (pw, ph) = sc.size();
-            let mut hoffs = DEFAULT_PADDING;
-            let mut voffs = DEFAULT_PADDING;
-            let mut hdiff = hoffs + DEFAULT_PADDING;
-            let mut vdiff = voffs + DEFAULT_PADDING;
-            let inner_h = pw as i32 - hdiff;
-            let inner_v = ph as i32 - vdiff;
-
-            let mut bm: wingdi::BITMAP = mem::zeroed();
+            let size = sc.frame_size();
+            let (inner_h, inner_v) = (size.0 as i32, size.1 as i32);
+
+            sc.state.frame(size, |frame| bitmap_from_rgba(frame.pixels()));
+            let bmp = sc.state.renditions.shown().map_or(ptr::null_mut(), |bitmap| bitmap.0);
+            if bmp.is_null() {
+                // the next paint retries
+                sc.state.renditions.clear();
+            }
+
             let mut ps: winuser::PAINTSTRUCT = mem::zeroed();
+            let hdc = winuser::BeginPaint(hwnd, &mut ps);
+            if !bmp.is_null() {
+                let hdc_mem = wingdi::CreateCompatibleDC(hdc);
+                wingdi::SelectObject(hdc_mem, bmp as *mut c_void);
+
+                let blendfunc = wingdi::BLENDFUNCTION {
+                    BlendOp: 0,
+                    BlendFlags: 0,
+                    SourceConstantAlpha: 255,
+                    AlphaFormat: wingdi::AC_SRC_ALPHA,
+                };
+                wingdi::GdiAlphaBlend(hdc, DEFAULT_PADDING, DEFAULT_PADDING, inner_h, inner_v, hdc_mem, 0, 0, inner_h, inner_v, blendfunc);
 
-            let mut hdc = winuser::BeginPaint(hwnd, &mut ps);
-            let mut hdc_mem = wingdi::CreateCompatibleDC(hdc);
-            wingdi::SelectObject(hdc_mem, sc.bmp as *mut c_void); //let hbm_old =
-            wingdi::GetObjectW(sc.bmp as *mut c_void, mem::size_of::<wingdi::BITMAP>() as i32, &mut bm as *mut _ as *mut c_void);
-
-            let (wrate, hrate) = (inner_h as f32 / bm.bmWidth as f32, inner_v as f32 / bm.bmHeight as f32);
-            let less_rate = fmin(wrate, hrate);
-
-            let blendfunc = wingdi::BLENDFUNCTION {
-                BlendOp: 0,
-                BlendFlags: 0,
-                SourceConstantAlpha: 255,
-                AlphaFormat: 1,
-            };
-
-            let (dst_x, dst_y, dst_w, dst_h, src_x, src_y, src_w, src_h) = match sc.scale {
-                super::ScalePolicy::FitCenter => {
-                    let bm_h = (bm.bmWidth as f32 * less_rate) as i32;
-                    let bm_v = (bm.bmHeight as f32 * less_rate) as i32;
-                    let xoffs = (pw as i32 - bm_h) / 2;
-                    let yoffs = (ph as i32 - bm_v) / 2;
-                    (xoffs, yoffs, bm_h, bm_v, 0, 0, bm.bmWidth, bm.bmHeight)
-                }
-                super::ScalePolicy::CropCenter => {
-                    let half_diff_h = (bm.bmWidth - pw as i32) / 2;
-                    let half_diff_v = (bm.bmHeight - ph as i32) / 2;
-                    (
-                        hoffs + cmp::min(hoffs, half_diff_h).abs(),
-                        voffs + cmp::min(voffs, half_diff_v).abs(),
-                        cmp::min(pw as i32, bm.bmWidth),
-                        cmp::min(ph as i32, bm.bmHeight),
-                        cmp::max(0, half_diff_h),
-                        cmp::max(0, half_diff_v),
-                        cmp::min(bm.bmWidth, inner_h),
-                        cmp::min(bm.bmHeight, inner_v),
-                    )
-                }
-            };
-            println!("{}/{}/{}/{} s {}/{}/{}/{}", dst_x, dst_y, dst_w, dst_h, src_x, src_y, src_w, src_h);
-            wingdi::GdiAlphaBlend(hdc, dst_x, dst_y, dst_w, dst_h, hdc_mem, src_x, src_y, src_w, src_h, blendfunc);
-
-            wingdi::DeleteDC(hdc_mem);
+                wingdi::DeleteDC(hdc_mem);
+            }
             winuser::EndPaint(hwnd, &ps);
         }
         _ => {}
@@ -287,20 +333,4 @@ unsafe extern "system" fn handler(hwnd: windef::HWND, msg: minwindef::UINT, wpar
     commctrl::DefSubclassProc(hwnd, msg, wparam, lparam)
 }
 
-fn fmin(a: f32, b: f32) -> f32 {
-    if a < b {
-        a
-    } else {
-        b
-    }
-}
-/*fn fmax(a: f32, b: f32) -> f32 {
-    // leave for future non-centered fit
-    if a > b {
-        a
-    } else {
-        b
-    }
-}*/
-
 impl_all_defaults!(Image);
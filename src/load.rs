@@ -0,0 +1,722 @@
+use super::callbacks;
+#[cfg(feature = "svg")]
+use super::vector;
+use super::{Image, ImageError, ImageSource, Orientation};
+
+use gif::{self, SetParameter};
+use image::{self, DynamicImage, ImageFormat, RgbaImage};
+
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+/// Largest amount of pixels a content may have, anything bigger is refused before being decoded.
+pub const MAX_PIXELS: u64 = 1 << 28;
+/// How often (in milliseconds) the UI thread checks whether the background decoding has finished.
+pub const POLL_INTERVAL: u32 = 16;
+/// Frames with a shorter delay (in milliseconds) are shown for `DEFAULT_FRAME_DELAY`, the way the browsers do.
+const MIN_FRAME_DELAY: u64 = 20;
+const DEFAULT_FRAME_DELAY: u64 = 100;
+/// Densities of the resolution variants looked for next to a file.
+const VARIANT_DENSITIES: [u32; 2] = [2, 3];
+
+pub type Decode = Box<FnMut() -> Result<ImageSource, ImageError> + Send>;
+
+/// Background decoding state of an image, together with the callbacks fired when it ends.
+pub struct Loader {
+    pending: Option<Receiver<Result<ImageSource, ImageError>>>,
+    pub on_load: Option<callbacks::Load>,
+    pub on_error: Option<callbacks::LoadError>,
+}
+
+impl Loader {
+    pub fn new() -> Loader {
+        Loader {
+            pending: None,
+            on_load: None,
+            on_error: None,
+        }
+    }
+    /// Runs `decode` on a worker thread. The result of the previous run, if it has not been taken yet, is dropped.
+    pub fn start(&mut self, mut decode: Decode) {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // the receiving side is gone if the loading was cancelled meanwhile
+            let _ = sender.send(decode());
+        });
+        self.pending = Some(receiver);
+    }
+    pub fn cancel(&mut self) {
+        self.pending = None;
+    }
+    pub fn is_loading(&self) -> bool {
+        self.pending.is_some()
+    }
+    /// Takes the result if the worker thread has finished.
+    pub fn poll(&mut self) -> Option<Result<ImageSource, ImageError>> {
+        let result = match self.pending {
+            Some(ref receiver) => match receiver.try_recv() {
+                Ok(result) => result,
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => Err(ImageError::Decode("decoding thread panicked".into())),
+            },
+            None => return None,
+        };
+        self.pending = None;
+        Some(result)
+    }
+}
+
+/// Shows the loaded content in `image` and fires the matching callback of `loader`, which is expected to belong to `image`.
+pub fn deliver(image: &mut Image, loader: &mut Loader, result: Result<ImageSource, ImageError>) {
+    match result {
+        Ok(source) => {
+            image.set_source(source);
+            if let Some(ref mut cb) = loader.on_load {
+                (cb.as_mut())(image);
+            }
+        }
+        Err(e) => {
+            if let Some(ref mut cb) = loader.on_error {
+                (cb.as_mut())(image, &e);
+            }
+        }
+    }
+}
+
+/// Decodes the file at `path` together with its resolution variants next to it, named `name@2x.png` and `name@3x.png`
+/// after `name.png`. A file named that way itself is decoded alone, as content of that density.
+pub fn from_path(path: &Path) -> Result<ImageSource, ImageError> {
+    let density = density(path);
+    let source = from_bytes(&read(File::open(path)?)?)?;
+    if density != 1 {
+        return Ok(source.with_density(density as f32));
+    }
+    let mut variants = vec![source];
+    for (path, density) in variant_paths(path) {
+        // a variant that cannot be decoded is left out, the content is shown from the other ones
+        if let Ok(variant) = File::open(&path).map_err(ImageError::from).and_then(read).and_then(|bytes| from_bytes(&bytes)) {
+            variants.push(variant.with_density(density as f32));
+        }
+    }
+    Ok(ImageSource::with_variants(variants))
+}
+
+/// Files the resolution variants of the file at `path` are looked for in, with their densities.
+/// None for a file named as a variant itself.
+pub fn variant_paths(path: &Path) -> Vec<(PathBuf, u32)> {
+    if density(path) != 1 {
+        return Vec::new();
+    }
+    VARIANT_DENSITIES.iter().map(|&density| (variant_path(path, density), density)).collect()
+}
+
+/// Density of the file at `path` according to its `@2x`-like name suffix, 1 without one.
+fn density(path: &Path) -> u32 {
+    let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("");
+    match stem.rfind('@') {
+        Some(at) if stem.ends_with('x') => stem[at + 1..stem.len() - 1].parse().ok().filter(|&density| density > 0).unwrap_or(1),
+        _ => 1,
+    }
+}
+
+fn variant_path(path: &Path, density: u32) -> PathBuf {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(extension) => format!("{}@{}x.{}", stem, density, extension.to_string_lossy()),
+        None => format!("{}@{}x", stem, density),
+    };
+    path.with_file_name(name)
+}
+
+pub fn read<R: Read>(mut reader: R) -> Result<Vec<u8>, ImageError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Decodes content of any format the `image` crate reads, GIF and APNG with all their frames.
+/// Animated WebP is not supported: the `image` WebP decoder only reads still lossy images, in gray, so the frames of
+/// an animated one could not be decoded either. It fails with `ImageError::Decode` rather than showing nothing.
+pub fn from_bytes(bytes: &[u8]) -> Result<ImageSource, ImageError> {
+    if is_animated_webp(bytes) {
+        return Err(ImageError::Decode("animated WebP is not supported".into()));
+    }
+    let format = image::guess_format(bytes);
+    #[cfg(feature = "svg")]
+    {
+        if format.is_err() && vector::is_svg(bytes) {
+            return ImageSource::from_svg(bytes);
+        }
+    }
+    let format = format.map_err(|_| ImageError::UnsupportedFormat)?;
+    if let Some((width, height)) = dimensions(bytes, format) {
+        check_size(width, height)?;
+    }
+    match format {
+        ImageFormat::GIF => return from_gif(bytes),
+        ImageFormat::PNG if is_apng(bytes) => return from_apng(bytes),
+        _ => {}
+    }
+    let content = image::load(io::Cursor::new(bytes), format)?;
+    let (width, height) = {
+        use image::GenericImage;
+        content.dimensions()
+    };
+    check_size(width, height)?;
+    let orientation = orientation(bytes, format).unwrap_or(Orientation::Normal);
+    Ok(ImageSource::new(into_rgba(content)).with_orientation(orientation))
+}
+
+pub fn into_rgba(content: DynamicImage) -> RgbaImage {
+    match content {
+        DynamicImage::ImageRgba8(pixels) => pixels,
+        content => content.to_rgba(),
+    }
+}
+
+/// Decodes all the frames of a GIF, each one composed onto the logical screen over what the previous ones left there.
+fn from_gif(bytes: &[u8]) -> Result<ImageSource, ImageError> {
+    let mut decoder = gif::Decoder::new(bytes);
+    decoder.set(gif::ColorOutput::RGBA);
+    let mut reader = decoder.read_info().map_err(gif_error)?;
+    let (width, height) = (reader.width() as u32, reader.height() as u32);
+    let mut screen = RgbaImage::new(width, height);
+    let mut frames = Vec::new();
+    while let Some(frame) = reader.read_next_frame().map_err(gif_error)? {
+        // the frames are kept decoded, so all of them count against the limit
+        if width as u64 * height as u64 * (frames.len() as u64 + 1) > MAX_PIXELS {
+            return Err(ImageError::TooLarge { width: width, height: height });
+        }
+        let previous = if frame.dispose == gif::DisposalMethod::Previous { Some(screen.clone()) } else { None };
+        let (left, top, frame_width) = (frame.left as u32, frame.top as u32, frame.width as u32);
+        for (i, pixel) in frame.buffer.chunks(4).enumerate() {
+            let (x, y) = (left + i as u32 % frame_width, top + i as u32 / frame_width);
+            if pixel[3] > 0 && x < width && y < height {
+                screen.put_pixel(x, y, image::Rgba { data: [pixel[0], pixel[1], pixel[2], pixel[3]] });
+            }
+        }
+        frames.push((screen.clone(), frame_delay(frame.delay as u64 * 10)));
+
+        match frame.dispose {
+            gif::DisposalMethod::Background => {
+                for y in top..cmp::min(top + frame.height as u32, height) {
+                    for x in left..cmp::min(left + frame_width, width) {
+                        screen.put_pixel(x, y, image::Rgba { data: [0; 4] });
+                    }
+                }
+            }
+            _ => {
+                if let Some(previous) = previous {
+                    screen = previous;
+                }
+            }
+        }
+    }
+    if frames.is_empty() {
+        return Err(ImageError::Decode("GIF without frames".into()));
+    }
+    Ok(ImageSource::animated(frames))
+}
+
+/// Whether the PNG has the animation control chunk, which comes before the image data.
+/// Whether `bytes` are a WebP with the extended header, whose flags tell it is animated.
+fn is_animated_webp(bytes: &[u8]) -> bool {
+    bytes.get(0..4) == Some(&b"RIFF"[..]) && bytes.get(8..16) == Some(&b"WEBPVP8X"[..]) && bytes.get(20).map_or(false, |flags| flags & 0x02 != 0)
+}
+
+fn is_apng(bytes: &[u8]) -> bool {
+    png_chunks(bytes).iter().take_while(|&&(kind, _)| kind != b"IDAT").any(|&(kind, _)| kind == b"acTL")
+}
+
+/// Decodes all the frames of an APNG. Each one is decoded as a PNG of its own, made of its data and the chunks all of them
+/// share, then composed onto the canvas as its blend and dispose operations say. A default image without frame control
+/// is not part of the animation.
+fn from_apng(bytes: &[u8]) -> Result<ImageSource, ImageError> {
+    let chunks = png_chunks(bytes);
+    let header = match chunks.first() {
+        Some(&(b"IHDR", data)) if data.len() == 13 => data,
+        _ => return Err(ImageError::Decode("PNG without header".into())),
+    };
+    let (width, height) = (be32(&header[0..4]), be32(&header[4..8]));
+    // the palette and the color information, everything before the image data but the animation chunks
+    let shared = chunks.iter().skip(1).take_while(|&&(kind, _)| kind != b"IDAT").filter(|&&(kind, _)| kind != b"acTL" && kind != b"fcTL").collect::<Vec<_>>();
+
+    // the frame control of each frame, followed by its data in one IDAT or fdAT chunk or more
+    let mut controls: Vec<(&[u8], Vec<u8>)> = Vec::new();
+    for &(kind, data) in &chunks {
+        match (kind, controls.last_mut()) {
+            (b"fcTL", _) if data.len() == 26 => controls.push((data, Vec::new())),
+            (b"fcTL", _) => return Err(ImageError::Decode("malformed APNG frame control".into())),
+            (b"IDAT", Some(&mut (_, ref mut frame))) => frame.extend_from_slice(data),
+            // the sequence number comes first
+            (b"fdAT", Some(&mut (_, ref mut frame))) if data.len() > 4 => frame.extend_from_slice(&data[4..]),
+            _ => {}
+        }
+    }
+
+    let mut canvas = RgbaImage::new(width, height);
+    let mut frames = Vec::new();
+    for (control, data) in controls {
+        if width as u64 * height as u64 * (frames.len() as u64 + 1) > MAX_PIXELS {
+            return Err(ImageError::TooLarge { width: width, height: height });
+        }
+        let (frame_width, frame_height, left, top) = (be32(&control[4..8]), be32(&control[8..12]), be32(&control[12..16]), be32(&control[16..20]));
+        if left as u64 + frame_width as u64 > width as u64 || top as u64 + frame_height as u64 > height as u64 {
+            return Err(ImageError::Decode("APNG frame outside of the canvas".into()));
+        }
+        let mut png = PNG_SIGNATURE.to_vec();
+        let mut frame_header = header.to_vec();
+        frame_header[0..8].copy_from_slice(&control[4..12]);
+        push_chunk(&mut png, b"IHDR", &frame_header);
+        for &&(kind, data) in &shared {
+            push_chunk(&mut png, kind, data);
+        }
+        push_chunk(&mut png, b"IDAT", &data);
+        push_chunk(&mut png, b"IEND", &[]);
+        let frame = into_rgba(image::load_from_memory_with_format(&png, ImageFormat::PNG)?);
+        if frame.dimensions() != (frame_width, frame_height) {
+            return Err(ImageError::Decode("APNG frame of the wrong size".into()));
+        }
+
+        let (dispose, blend) = (control[24], control[25]);
+        let previous = if dispose == 2 { Some(canvas.clone()) } else { None };
+        for (x, y, &pixel) in frame.enumerate_pixels() {
+            let (x, y) = (left + x, top + y);
+            let pixel = if blend == 1 { over(*canvas.get_pixel(x, y), pixel) } else { pixel };
+            canvas.put_pixel(x, y, pixel);
+        }
+        let (delay_num, delay_den) = (be16(&control[20..22]) as u64, be16(&control[22..24]) as u64);
+        frames.push((canvas.clone(), frame_delay(delay_num * 1000 / if delay_den == 0 { 100 } else { delay_den })));
+
+        match dispose {
+            1 => {
+                for y in top..top + frame_height {
+                    for x in left..left + frame_width {
+                        canvas.put_pixel(x, y, image::Rgba { data: [0; 4] });
+                    }
+                }
+            }
+            // what was there before the frame, which is nothing for the first one
+            _ => {
+                if let Some(previous) = previous {
+                    canvas = previous;
+                }
+            }
+        }
+    }
+    if frames.is_empty() {
+        return Err(ImageError::Decode("APNG without frames".into()));
+    }
+    Ok(ImageSource::animated(frames))
+}
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// The type and the data of each chunk of a PNG, up to the first truncated one.
+fn png_chunks(bytes: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut chunks = Vec::new();
+    let mut at = PNG_SIGNATURE.len();
+    while let Some(length) = bytes.get(at..at + 4).map(be32) {
+        // the length, the type, the data and its CRC
+        let end = match (length as usize).checked_add(at + 12) {
+            Some(end) if end <= bytes.len() => end,
+            _ => break,
+        };
+        chunks.push((&bytes[at + 4..at + 8], &bytes[at + 8..end - 4]));
+        at = end;
+    }
+    chunks
+}
+
+fn push_chunk(png: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    let length = data.len() as u32;
+    png.extend_from_slice(&[(length >> 24) as u8, (length >> 16) as u8, (length >> 8) as u8, length as u8]);
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(kind.iter().chain(data));
+    png.extend_from_slice(&[(crc >> 24) as u8, (crc >> 16) as u8, (crc >> 8) as u8, crc as u8]);
+}
+
+fn crc32<'a, I: Iterator<Item = &'a u8>>(bytes: I) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { 0xEDB8_8320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn be16(bytes: &[u8]) -> u16 {
+    (bytes[0] as u16) << 8 | bytes[1] as u16
+}
+fn be32(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32
+}
+
+/// `src` composed over `dst`, neither of them premultiplied.
+fn over(dst: image::Rgba<u8>, src: image::Rgba<u8>) -> image::Rgba<u8> {
+    let source = src.data[3] as u32;
+    let destination = dst.data[3] as u32 * (255 - source) / 255;
+    let alpha = source + destination;
+    if alpha == 0 {
+        return image::Rgba { data: [0; 4] };
+    }
+    let channel = |i: usize| ((src.data[i] as u32 * source + dst.data[i] as u32 * destination + alpha / 2) / alpha) as u8;
+    image::Rgba { data: [channel(0), channel(1), channel(2), alpha as u8] }
+}
+
+fn frame_delay(millis: u64) -> Duration {
+    Duration::from_millis(if millis < MIN_FRAME_DELAY { DEFAULT_FRAME_DELAY } else { millis })
+}
+
+fn gif_error(e: gif::DecodingError) -> ImageError {
+    match e {
+        gif::DecodingError::Io(e) => ImageError::Io(e),
+        e => ImageError::Decode(e.to_string()),
+    }
+}
+
+pub fn check_size(width: u32, height: u32) -> Result<(), ImageError> {
+    if width as u64 * height as u64 > MAX_PIXELS {
+        Err(ImageError::TooLarge { width: width, height: height })
+    } else {
+        Ok(())
+    }
+}
+
+/// Reads the image size from the header, for the formats where it is cheap.
+fn dimensions(bytes: &[u8], format: ImageFormat) -> Option<(u32, u32)> {
+    let be32 = |at: usize| bytes_at(bytes, at, 4).map(|b| (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32);
+    let be16 = |at: usize| bytes_at(bytes, at, 2).map(|b| (b[0] as u32) << 8 | b[1] as u32);
+    let le16 = |at: usize| bytes_at(bytes, at, 2).map(|b| (b[1] as u32) << 8 | b[0] as u32);
+    let le32 = |at: usize| bytes_at(bytes, at, 4).map(|b| (b[3] as u32) << 24 | (b[2] as u32) << 16 | (b[1] as u32) << 8 | b[0] as u32);
+
+    match format {
+        // IHDR is always the first chunk
+        ImageFormat::PNG => Some((be32(16)?, be32(20)?)),
+        // logical screen descriptor
+        ImageFormat::GIF => Some((le16(6)?, le16(8)?)),
+        // BITMAPINFOHEADER, height is negative for top-down bitmaps
+        ImageFormat::BMP => Some((le32(18)?, (le32(22)? as i32).abs() as u32)),
+        ImageFormat::JPEG => {
+            // walk the segments up to the first start-of-frame one
+            let mut at = 2;
+            loop {
+                let marker = be16(at)?;
+                let length = be16(at + 2)? as usize;
+                match marker {
+                    0xFFC0...0xFFC3 | 0xFFC5...0xFFC7 | 0xFFC9...0xFFCB | 0xFFCD...0xFFCF => return Some((be16(at + 7)?, be16(at + 5)?)),
+                    _ => at = at.checked_add(2 + length)?,
+                }
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Reads the orientation from the EXIF data of a JPEG (in the APP1 segment) or of a PNG (in the eXIf chunk).
+fn orientation(bytes: &[u8], format: ImageFormat) -> Option<Orientation> {
+    let be16 = |at: usize| bytes_at(bytes, at, 2).map(|b| (b[0] as usize) << 8 | b[1] as usize);
+    let be32 = |at: usize| bytes_at(bytes, at, 4).map(|b| (b[0] as usize) << 24 | (b[1] as usize) << 16 | (b[2] as usize) << 8 | b[3] as usize);
+
+    let exif = match format {
+        ImageFormat::JPEG => {
+            // the metadata segments come before the image data
+            let mut at = 2;
+            loop {
+                let marker = be16(at)?;
+                let length = be16(at + 2)?;
+                match marker {
+                    0xFFE1 if bytes.get(at + 4..at + 10) == Some(&b"Exif\0\0"[..]) => break bytes.get(at + 10..at + 2 + length)?,
+                    0xFFDA | 0xFFD9 => return None,
+                    _ => at = at.checked_add(2 + length)?,
+                }
+            }
+        }
+        ImageFormat::PNG => {
+            // chunks after the signature, the data of each one followed by its CRC
+            let mut at = 8;
+            loop {
+                let length = be32(at)?;
+                match bytes.get(at + 4..at + 8)? {
+                    b"eXIf" => break bytes_at(bytes, at + 8, length)?,
+                    b"IDAT" | b"IEND" => return None,
+                    _ => at = at.checked_add(12)?.checked_add(length)?,
+                }
+            }
+        }
+        _ => return None,
+    };
+    Orientation::from_exif(exif_orientation(exif)?)
+}
+
+/// Value of the orientation tag in the first IFD of the TIFF structure EXIF data is stored in.
+fn exif_orientation(tiff: &[u8]) -> Option<u16> {
+    let little_endian = match tiff.get(0..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let u16_at = |at: usize| bytes_at(tiff, at, 2).map(|b| if little_endian { (b[1] as u16) << 8 | b[0] as u16 } else { (b[0] as u16) << 8 | b[1] as u16 });
+    let u32_at = |at: usize| Some((u16_at(at)? as u32, u16_at(at + 2)? as u32)).map(|(a, b)| if little_endian { b << 16 | a } else { a << 16 | b });
+
+    if u16_at(2)? != 42 {
+        return None;
+    }
+    let ifd = u32_at(4)? as usize;
+    for entry in 0..u16_at(ifd)? as usize {
+        let at = ifd.checked_add(2 + entry * 12)?;
+        // tag, then type, which is SHORT for the orientation, then the count and the value itself
+        if u16_at(at)? == 0x0112 {
+            return if u16_at(at + 2)? == 3 { u16_at(at + 8) } else { None };
+        }
+    }
+    None
+}
+
+/// The `length` bytes of `bytes` at `at`, `None` unless all of them are there, however far past the end `at` is.
+fn bytes_at(bytes: &[u8], at: usize, length: usize) -> Option<&[u8]> {
+    bytes.get(at..at.checked_add(length)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{png, ColorType, Rgba};
+
+    use std::{env, fs, process};
+
+    fn encode(frame: &RgbaImage) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        png::PNGEncoder::new(&mut bytes).encode(frame, frame.width(), frame.height(), ColorType::RGBA(8)).unwrap();
+        bytes
+    }
+    fn frame_control(sequence: u32, frame: &RgbaImage, left: u32, top: u32, delay: (u16, u16), dispose: u8, blend: u8) -> Vec<u8> {
+        let mut control = Vec::new();
+        for &value in &[sequence, frame.width(), frame.height(), left, top] {
+            control.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+        }
+        for &value in &[delay.0, delay.1] {
+            control.extend_from_slice(&[(value >> 8) as u8, value as u8]);
+        }
+        control.extend_from_slice(&[dispose, blend]);
+        control
+    }
+    /// The data of the first chunk of `kind` in the PNG.
+    fn chunk(bytes: &[u8], kind: &[u8]) -> Vec<u8> {
+        png_chunks(bytes).into_iter().find(|&(k, _)| k == kind).unwrap().1.to_vec()
+    }
+
+    /// A 2x2 APNG of `first` as its default image and first frame, and `second` blended over its bottom right pixel.
+    fn apng(first: &RgbaImage, second: &RgbaImage) -> Vec<u8> {
+        let (first_png, second_png) = (encode(first), encode(second));
+        let mut apng = PNG_SIGNATURE.to_vec();
+        push_chunk(&mut apng, b"IHDR", &chunk(&first_png, b"IHDR"));
+        push_chunk(&mut apng, b"acTL", &[0, 0, 0, 2, 0, 0, 0, 0]);
+        push_chunk(&mut apng, b"fcTL", &frame_control(0, first, 0, 0, (1, 4), 0, 0));
+        push_chunk(&mut apng, b"IDAT", &chunk(&first_png, b"IDAT"));
+        push_chunk(&mut apng, b"fcTL", &frame_control(1, second, 1, 1, (0, 0), 0, 1));
+        let mut data = vec![0, 0, 0, 2];
+        data.extend_from_slice(&chunk(&second_png, b"IDAT"));
+        push_chunk(&mut apng, b"fdAT", &data);
+        push_chunk(&mut apng, b"IEND", &[]);
+        apng
+    }
+
+    #[test]
+    fn crc() {
+        // the CRC of the IEND chunk every PNG ends with
+        assert_eq!(crc32(b"IEND".iter()), 0xAE42_6082);
+    }
+
+    #[test]
+    fn apng_frames() {
+        let first = RgbaImage::from_pixel(2, 2, Rgba { data: [0, 0, 255, 255] });
+        let second = RgbaImage::from_pixel(1, 1, Rgba { data: [255, 0, 0, 128] });
+        let bytes = apng(&first, &second);
+        assert!(is_apng(&bytes));
+        assert!(!is_apng(&encode(&first)));
+
+        let source = from_bytes(&bytes).unwrap();
+        assert_eq!(source.frame_count(), 2);
+        assert_eq!(source.frame(0).unwrap().pixels().clone().into_raw(), first.into_raw());
+        let second = source.frame(1).unwrap();
+        assert_eq!(*second.pixels().get_pixel(0, 0), Rgba { data: [0, 0, 255, 255] });
+        assert_eq!(*second.pixels().get_pixel(1, 1), Rgba { data: [128, 0, 127, 255] });
+        assert_eq!(source.frame_delay(0), Some(Duration::from_millis(250)));
+        // a zero delay is played as browsers play it
+        assert_eq!(source.frame_delay(1), Some(Duration::from_millis(DEFAULT_FRAME_DELAY)));
+    }
+
+    #[test]
+    fn apng_frame_outside() {
+        let first = RgbaImage::new(2, 2);
+        let bytes = apng(&first, &RgbaImage::new(2, 2));
+        match from_bytes(&bytes) {
+            Err(ImageError::Decode(_)) => {}
+            other => panic!("{:?}", other.map(|source| source.frame_count())),
+        }
+    }
+
+    #[test]
+    fn bad_variant() {
+        let dir = env::temp_dir().join(format!("plygui-image-load-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.png");
+        fs::write(&path, encode(&RgbaImage::new(1, 1))).unwrap();
+        fs::write(dir.join("a@2x.png"), b"not a png").unwrap();
+        fs::write(dir.join("a@3x.png"), encode(&RgbaImage::new(3, 3))).unwrap();
+        let source = from_path(&path);
+        fs::remove_dir_all(&dir).unwrap();
+        // the broken variant is left out, the others are kept
+        let source = source.unwrap();
+        assert_eq!(source.dimensions(), (1, 1));
+        assert_eq!(source.variant(2.0).dimensions(), (3, 3));
+        assert_eq!(source.variant(2.0).density(), 3.0);
+    }
+
+    /// TIFF structure whose first IFD holds the image width, then the orientation tag of `value`.
+    fn tiff(little_endian: bool, value: u16) -> Vec<u8> {
+        let u16s = |value: u16| if little_endian { [value as u8, (value >> 8) as u8] } else { [(value >> 8) as u8, value as u8] };
+        let u32s = |value: u32| {
+            let (high, low) = (u16s((value >> 16) as u16), u16s(value as u16));
+            if little_endian { [low[0], low[1], high[0], high[1]] } else { [high[0], high[1], low[0], low[1]] }
+        };
+        let mut tiff = if little_endian { b"II".to_vec() } else { b"MM".to_vec() };
+        tiff.extend_from_slice(&u16s(42));
+        tiff.extend_from_slice(&u32s(8));
+        tiff.extend_from_slice(&u16s(2));
+        // tag, type, count and value: the width is a LONG
+        tiff.extend_from_slice(&u16s(0x0100));
+        tiff.extend_from_slice(&u16s(4));
+        tiff.extend_from_slice(&u32s(1));
+        tiff.extend_from_slice(&u32s(640));
+        // and the orientation a SHORT, at the start of its value field
+        tiff.extend_from_slice(&u16s(0x0112));
+        tiff.extend_from_slice(&u16s(3));
+        tiff.extend_from_slice(&u32s(1));
+        tiff.extend_from_slice(&u16s(value));
+        tiff.extend_from_slice(&[0, 0]);
+        // no next IFD
+        tiff.extend_from_slice(&u32s(0));
+        tiff
+    }
+    fn segment(jpeg: &mut Vec<u8>, marker: u8, data: &[u8]) {
+        let length = data.len() + 2;
+        jpeg.extend_from_slice(&[0xFF, marker, (length >> 8) as u8, length as u8]);
+        jpeg.extend_from_slice(data);
+    }
+    /// A JPEG of 3x2 with a JFIF segment, then the EXIF one holding `tiff`, up to its scan.
+    fn jpeg(tiff: &[u8]) -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        segment(&mut jpeg, 0xE0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        segment(&mut jpeg, 0xE1, &[&b"Exif\0\0"[..], tiff].concat());
+        segment(&mut jpeg, 0xC0, &[8, 0, 2, 0, 3, 1, 1, 0x11, 0]);
+        segment(&mut jpeg, 0xDA, &[]);
+        jpeg
+    }
+    /// A PNG of 3x2 with `exif` in an eXIf chunk before its image data.
+    fn png(exif: &[u8]) -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        for (kind, data) in png_chunks(&encode(&RgbaImage::new(3, 2))) {
+            if kind == b"IDAT" {
+                push_chunk(&mut png, b"eXIf", exif);
+            }
+            push_chunk(&mut png, kind, data);
+        }
+        png
+    }
+
+    #[test]
+    fn exif_byte_orders() {
+        for &little_endian in &[true, false] {
+            let tiff = tiff(little_endian, 6);
+            assert_eq!(tiff.len(), 38);
+            assert_eq!(exif_orientation(&tiff), Some(6));
+            // the value ends 2 bytes into its field of the second entry
+            for length in 0..32 {
+                assert_eq!(exif_orientation(&tiff[..length]), None, "{} {}", little_endian, length);
+            }
+            assert_eq!(exif_orientation(&tiff[..32]), Some(6));
+
+            // the first IFD past the end
+            let mut far = tiff.clone();
+            far[4..8].copy_from_slice(&[0xFF; 4]);
+            assert_eq!(exif_orientation(&far), None);
+            // an orientation which is not a SHORT
+            let mut long = tiff.clone();
+            long[24..26].copy_from_slice(if little_endian { &[4, 0] } else { &[0, 4] });
+            assert_eq!(exif_orientation(&long), None);
+        }
+        assert_eq!(exif_orientation(b"MM\0\x2b\0\0\0\x08"), None);
+        assert_eq!(exif_orientation(b"XX\0\x2a\0\0\0\x08"), None);
+    }
+
+    #[test]
+    fn jpeg_orientation() {
+        for &little_endian in &[true, false] {
+            let tiff = tiff(little_endian, 8);
+            let jpeg = jpeg(&tiff);
+            assert_eq!(orientation(&jpeg, ImageFormat::JPEG), Some(Orientation::Rotate270));
+            assert_eq!(dimensions(&jpeg, ImageFormat::JPEG), Some((3, 2)));
+            // cut anywhere within the EXIF segment, or before the start of frame
+            let exif_end = 2 + 18 + 10 + tiff.len();
+            for length in 0..jpeg.len() {
+                let cut = &jpeg[..length];
+                if length < exif_end {
+                    assert_eq!(orientation(cut, ImageFormat::JPEG), None, "{}", length);
+                }
+                if length < exif_end + 9 {
+                    assert_eq!(dimensions(cut, ImageFormat::JPEG), None, "{}", length);
+                }
+            }
+        }
+        // a segment length running far past the end
+        let mut jpeg = jpeg(&tiff(true, 8));
+        jpeg[4..6].copy_from_slice(&[0xFF, 0xFF]);
+        assert_eq!(orientation(&jpeg, ImageFormat::JPEG), None);
+        assert_eq!(dimensions(&jpeg, ImageFormat::JPEG), None);
+    }
+
+    #[test]
+    fn png_orientation() {
+        for &little_endian in &[true, false] {
+            let tiff = tiff(little_endian, 3);
+            let png = png(&tiff);
+            assert_eq!(orientation(&png, ImageFormat::PNG), Some(Orientation::Rotate180));
+            // cut anywhere before the end of the eXIf data
+            let exif_end = 8 + 25 + 8 + tiff.len();
+            for length in 0..exif_end {
+                assert_eq!(orientation(&png[..length], ImageFormat::PNG), None, "{}", length);
+            }
+        }
+        // chunk lengths running far past the end
+        let mut png = png(&tiff(true, 3));
+        png[33..37].copy_from_slice(&[0xFF; 4]);
+        assert_eq!(orientation(&png, ImageFormat::PNG), None);
+        png[8..12].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xF0]);
+        assert_eq!(orientation(&png, ImageFormat::PNG), None);
+    }
+
+    #[test]
+    fn animated_webp() {
+        // the RIFF header, then the extended one with the animation flag, then the canvas size
+        let mut webp = b"RIFF\x1a\0\0\0WEBPVP8X\x0a\0\0\0".to_vec();
+        webp.extend_from_slice(&[0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(is_animated_webp(&webp));
+        match from_bytes(&webp) {
+            Err(ImageError::Decode(message)) => assert!(message.contains("WebP")),
+            other => panic!("{:?}", other.map(|source| source.dimensions())),
+        }
+        webp[20] = 0x10;
+        assert!(!is_animated_webp(&webp));
+    }
+}
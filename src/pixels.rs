@@ -0,0 +1,343 @@
+//! Conversion of the composed frames, which are always top-down, tightly packed, straight alpha RGBA,
+//! into the memory layout each toolkit expects.
+
+use super::geometry::Rect;
+use super::ImageSource;
+
+use image::RgbaImage;
+
+use std::borrow::Cow;
+
+/// Order of the color channels of a pixel in memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channels {
+    Rgba,
+    Bgra,
+    Argb,
+}
+
+/// Whether the color channels are stored as is, or already multiplied by the alpha.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alpha {
+    Straight,
+    Premultiplied,
+}
+
+/// Memory layout of a 32 bits per pixel bitmap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub channels: Channels,
+    pub alpha: Alpha,
+    /// Every row starts at a multiple of this many bytes.
+    pub row_align: usize,
+    /// Whether the last row comes first.
+    pub bottom_up: bool,
+}
+
+/// Layout of the frames themselves.
+pub const RGBA: Layout = Layout {
+    channels: Channels::Rgba,
+    alpha: Alpha::Straight,
+    row_align: 4,
+    bottom_up: false,
+};
+
+/// `cairo` image surface in `ARGB32`, whose pixels are native-endian 32 bit values.
+#[cfg(all(feature = "gtk3", target_endian = "little"))]
+pub const CAIRO: Layout = Layout {
+    channels: Channels::Bgra,
+    alpha: Alpha::Premultiplied,
+    row_align: 4,
+    bottom_up: false,
+};
+#[cfg(all(feature = "gtk3", target_endian = "big"))]
+pub const CAIRO: Layout = Layout {
+    channels: Channels::Argb,
+    alpha: Alpha::Premultiplied,
+    row_align: 4,
+    bottom_up: false,
+};
+
+/// `QImage` in `Format_RGBA8888`.
+#[cfg(feature = "qt5")]
+pub const QIMAGE: Layout = RGBA;
+
+/// Bottom-up 32 bpp DIB section, as `AlphaBlend` with `AC_SRC_ALPHA` expects it.
+#[cfg(all(target_os = "windows", feature = "win32"))]
+pub const DIB: Layout = Layout {
+    channels: Channels::Bgra,
+    alpha: Alpha::Premultiplied,
+    row_align: 4,
+    bottom_up: true,
+};
+
+/// `NSBitmapImageRep` in `NSAlphaNonpremultipliedBitmapFormat`.
+#[cfg(target_os = "macos")]
+pub const BITMAP_REP: Layout = RGBA;
+
+impl Layout {
+    /// Amount of bytes from the start of a row to the start of the next one.
+    pub fn stride(&self, width: u32) -> usize {
+        let row = width as usize * 4;
+        (row + self.row_align - 1) / self.row_align * self.row_align
+    }
+    /// Whether the frame bytes can be used as they are.
+    pub fn is_rgba(&self, width: u32) -> bool {
+        self.channels == Channels::Rgba && self.alpha == Alpha::Straight && !self.bottom_up && self.stride(width) == width as usize * 4
+    }
+}
+
+/// Bytes of `frame` in `layout`, moving the frame buffer out if it is in `layout` already and not shared.
+pub fn into_layout(frame: ImageSource, layout: Layout) -> Vec<u8> {
+    let (width, height) = frame.dimensions();
+    if layout.is_rgba(width) {
+        return frame.into_raw();
+    }
+    let mut bytes = vec![0; layout.stride(width) * height as usize];
+    write(frame.pixels(), layout, &mut bytes);
+    bytes
+}
+
+/// Bytes of `frame` in `layout`, borrowing the frame buffer if it is in `layout` already.
+pub fn as_layout(frame: &ImageSource, layout: Layout) -> Cow<[u8]> {
+    let (width, height) = frame.dimensions();
+    if layout.is_rgba(width) {
+        return Cow::Borrowed(frame.as_ref());
+    }
+    let mut bytes = vec![0; layout.stride(width) * height as usize];
+    write(frame.pixels(), layout, &mut bytes);
+    Cow::Owned(bytes)
+}
+
+/// Writes `frame` in `layout` into `dst`, which must hold at least `layout.stride(width) * height` bytes.
+pub fn write(frame: &RgbaImage, layout: Layout, dst: &mut [u8]) {
+    let (width, height) = frame.dimensions();
+    write_rect(frame, Rect::new(0, 0, width as i32, height as i32), layout, dst, (width, height));
+}
+
+/// Writes `part`, the `rect` part of a frame of `size`, into `dst` holding that frame in `layout`, leaving everything
+/// outside of `rect` as it is. `rect` must lie within the frame.
+pub fn write_rect(part: &RgbaImage, rect: Rect, layout: Layout, dst: &mut [u8], size: (u32, u32)) {
+    let (width, height) = size;
+    let stride = layout.stride(width);
+    assert!(dst.len() >= stride * height as usize);
+    assert!(rect.x >= 0 && rect.y >= 0 && rect.x + rect.width <= width as i32 && rect.y + rect.height <= height as i32);
+    assert_eq!(part.dimensions(), (rect.width as u32, rect.height as u32));
+    if rect.is_empty() {
+        return;
+    }
+    let part: &[u8] = part;
+    let (x, row) = (rect.x as usize * 4, rect.width as usize * 4);
+    for (i, src) in part.chunks(row).enumerate() {
+        let y = rect.y as usize + i;
+        let y = if layout.bottom_up { height as usize - 1 - y } else { y };
+        let dst = &mut dst[y * stride + x..y * stride + x + row];
+        for (s, d) in src.chunks(4).zip(dst.chunks_mut(4)) {
+            let (r, g, b, a) = match layout.alpha {
+                Alpha::Straight => (s[0], s[1], s[2], s[3]),
+                Alpha::Premultiplied => (mul(s[0], s[3]), mul(s[1], s[3]), mul(s[2], s[3]), s[3]),
+            };
+            match layout.channels {
+                Channels::Rgba => d.copy_from_slice(&[r, g, b, a]),
+                Channels::Bgra => d.copy_from_slice(&[b, g, r, a]),
+                Channels::Argb => d.copy_from_slice(&[a, r, g, b]),
+            }
+        }
+    }
+}
+
+/// Multiplies the color channels by the alpha, so filtering does not bleed the color of the transparent pixels.
+pub fn premultiply(frame: &mut RgbaImage) {
+    for p in frame.pixels_mut() {
+        let a = p.data[3];
+        for c in &mut p.data[..3] {
+            *c = mul(*c, a);
+        }
+    }
+}
+
+/// Undoes `premultiply`.
+pub fn unpremultiply(frame: &mut RgbaImage) {
+    for p in frame.pixels_mut() {
+        let a = p.data[3] as u32;
+        for c in &mut p.data[..3] {
+            *c = if a == 0 { 0 } else { ((*c as u32 * 255 + a / 2) / a).min(255) as u8 };
+        }
+    }
+}
+
+/// `c * a / 255`, rounded.
+fn mul(c: u8, a: u8) -> u8 {
+    let t = c as u32 * a as u32 + 128;
+    ((t + (t >> 8)) >> 8) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::Rgba;
+
+    /// Premultiplied bottom-up BGRA, as the DIB sections are.
+    const DIB: Layout = Layout {
+        channels: Channels::Bgra,
+        alpha: Alpha::Premultiplied,
+        row_align: 4,
+        bottom_up: true,
+    };
+    /// Rows padded to 16 bytes.
+    const PADDED: Layout = Layout {
+        channels: Channels::Rgba,
+        alpha: Alpha::Straight,
+        row_align: 16,
+        bottom_up: false,
+    };
+
+    fn frame() -> RgbaImage {
+        let pixels = [[255, 0, 0, 255], [0, 255, 0, 128], [0, 0, 255, 0], [10, 20, 30, 255], [200, 100, 50, 64], [1, 2, 3, 4]];
+        RgbaImage::from_fn(3, 2, |x, y| Rgba { data: pixels[(y * 3 + x) as usize] })
+    }
+
+    /// Reads `bytes` in `layout` back into a frame.
+    fn read(bytes: &[u8], layout: Layout, width: u32, height: u32) -> RgbaImage {
+        let stride = layout.stride(width);
+        let mut frame = RgbaImage::from_fn(width, height, |x, y| {
+            let y = if layout.bottom_up { height - 1 - y } else { y };
+            let p = &bytes[y as usize * stride + x as usize * 4..];
+            match layout.channels {
+                Channels::Rgba => Rgba { data: [p[0], p[1], p[2], p[3]] },
+                Channels::Bgra => Rgba { data: [p[2], p[1], p[0], p[3]] },
+                Channels::Argb => Rgba { data: [p[1], p[2], p[3], p[0]] },
+            }
+        });
+        if layout.alpha == Alpha::Premultiplied {
+            unpremultiply(&mut frame);
+        }
+        frame
+    }
+
+    #[test]
+    fn rgba() {
+        let frame = frame();
+        assert!(RGBA.is_rgba(3));
+        assert_eq!(into_layout(ImageSource::new(frame.clone()), RGBA), frame.clone().into_raw());
+        let source = ImageSource::new(frame.clone());
+        match as_layout(&source, RGBA) {
+            Cow::Borrowed(bytes) => assert_eq!(bytes, &*frame.clone().into_raw()),
+            Cow::Owned(_) => panic!("frame bytes copied"),
+        }
+        assert_eq!(read(&into_layout(source, RGBA), RGBA, 3, 2).into_raw(), frame.into_raw());
+    }
+
+    #[test]
+    fn dib() {
+        assert!(!DIB.is_rgba(3));
+        assert_eq!(DIB.stride(3), 12);
+        let bytes = into_layout(ImageSource::new(frame()), DIB);
+        let expected = [
+            30, 20, 10, 255, 13, 25, 50, 64, 0, 0, 0, 4,
+            0, 0, 255, 255, 0, 128, 0, 128, 0, 0, 0, 0,
+        ];
+        assert_eq!(bytes, &expected[..]);
+        let back = read(&bytes, DIB, 3, 2);
+        // the opaque and the transparent pixels come back as they were, the others close to it
+        for (back, pixel) in back.pixels().zip(frame().pixels()) {
+            match pixel.data[3] {
+                0 => assert_eq!(back.data, [0; 4]),
+                255 => assert_eq!(back, pixel),
+                a => {
+                    for c in 0..3 {
+                        assert!((back.data[c] as f32 - pixel.data[c] as f32).abs() <= 255.0 / (2.0 * a as f32) + 1.0, "{:?} {:?}", back, pixel);
+                    }
+                    assert_eq!(back.data[3], a);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn padded() {
+        assert!(!PADDED.is_rgba(3));
+        assert!(PADDED.is_rgba(4));
+        assert_eq!(PADDED.stride(3), 16);
+        let frame = frame();
+        let bytes = into_layout(ImageSource::new(frame.clone()), PADDED);
+        assert_eq!(bytes.len(), 32);
+        assert_eq!(&bytes[12..16], &[0; 4]);
+        assert_eq!(&bytes[28..32], &[0; 4]);
+        assert_eq!(read(&bytes, PADDED, 3, 2).into_raw(), frame.into_raw());
+    }
+
+    #[test]
+    fn rect() {
+        let frame = frame();
+        for &layout in &[RGBA, DIB, PADDED] {
+            let size = layout.stride(3) * 2;
+            let mut whole = vec![0xAA; size];
+            write(&frame, layout, &mut whole);
+
+            // written pixel by pixel, it is the same as written at once
+            let mut pieces = vec![0xAA; size];
+            for y in 0..2 {
+                for x in 0..3 {
+                    let piece = RgbaImage::from_pixel(1, 1, *frame.get_pixel(x as u32, y as u32));
+                    write_rect(&piece, Rect::new(x, y, 1, 1), layout, &mut pieces, (3, 2));
+                }
+            }
+            assert_eq!(pieces, whole);
+
+            // nothing outside of the rect is written
+            let mut part = vec![0xAA; size];
+            let piece = RgbaImage::from_fn(2, 1, |x, _| *frame.get_pixel(1 + x, 0));
+            write_rect(&piece, Rect::new(1, 0, 2, 1), layout, &mut part, (3, 2));
+            let row = if layout.bottom_up { 1 } else { 0 } * layout.stride(3);
+            for (i, (&part, &whole)) in part.iter().zip(whole.iter()).enumerate() {
+                if i >= row + 4 && i < row + 12 {
+                    assert_eq!(part, whole);
+                } else {
+                    assert_eq!(part, 0xAA);
+                }
+            }
+
+            let before = part.clone();
+            write_rect(&RgbaImage::new(0, 1), Rect::new(1, 1, 0, 1), layout, &mut part, (3, 2));
+            assert_eq!(part, before);
+        }
+    }
+
+    #[test]
+    fn premultiplied() {
+        let mut frame = RgbaImage::from_fn(4, 1, |x, _| {
+            let pixels = [[200, 100, 50, 255], [200, 100, 50, 0], [255, 255, 255, 128], [100, 50, 0, 51]];
+            Rgba { data: pixels[x as usize] }
+        });
+        premultiply(&mut frame);
+        assert_eq!(frame.get_pixel(0, 0).data, [200, 100, 50, 255]);
+        assert_eq!(frame.get_pixel(1, 0).data, [0, 0, 0, 0]);
+        assert_eq!(frame.get_pixel(2, 0).data, [128, 128, 128, 128]);
+        assert_eq!(frame.get_pixel(3, 0).data, [20, 10, 0, 51]);
+        unpremultiply(&mut frame);
+        assert_eq!(frame.get_pixel(0, 0).data, [200, 100, 50, 255]);
+        assert_eq!(frame.get_pixel(1, 0).data, [0, 0, 0, 0]);
+        assert_eq!(frame.get_pixel(2, 0).data, [255, 255, 255, 128]);
+        assert_eq!(frame.get_pixel(3, 0).data, [100, 50, 0, 51]);
+    }
+
+    #[test]
+    fn premultiplied_round_trip() {
+        for a in 1..256u32 {
+            for c in 0..256u32 {
+                let mut frame = RgbaImage::from_pixel(1, 1, Rgba { data: [c as u8, 0, 255, a as u8] });
+                premultiply(&mut frame);
+                assert!(frame.get_pixel(0, 0).data[0] as u32 <= a);
+                unpremultiply(&mut frame);
+                let back = frame.get_pixel(0, 0).data;
+                assert!((back[0] as f32 - c as f32).abs() <= 255.0 / (2.0 * a as f32) + 0.5, "{} {} {:?}", c, a, back);
+                assert_eq!(back[3], a as u8);
+                if a == 255 {
+                    assert_eq!(back, [c as u8, 0, 255, 255]);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,913 @@
+use super::animation::Player;
+use super::geometry::{self, Rect};
+use super::load::Loader;
+use super::pixels;
+use super::resample::resample;
+use super::saliency::Saliency;
+use super::sink;
+use super::transform::{self, Transform};
+#[cfg(feature = "svg")]
+use super::vector::{Tree, Vector};
+use super::{logical, Flip, Gravity, ImageSource, Interpolation, NinePatch, NinePatchFill, Placeholder, Region, ScalePolicy, SizeConstraints, TileMode, Trim};
+
+use image::{GenericImage, RgbaImage};
+
+#[cfg(feature = "svg")]
+use std::sync::Arc;
+use std::{cmp, mem};
+
+/// Everything besides the content that defines how the content is drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Style {
+    pub scale: ScalePolicy,
+    pub gravity: Gravity,
+    pub nine_patch: Option<NinePatch>,
+    pub focal_point: (f32, f32),
+    pub interpolation: Interpolation,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style {
+            scale: ScalePolicy::FitCenter,
+            gravity: Gravity::Center,
+            nine_patch: None,
+            focal_point: (0.5, 0.5),
+            interpolation: Interpolation::Bicubic,
+        }
+    }
+}
+
+/// Size an image with neither content nor a placeholder image wraps to.
+pub const EMPTY_SIZE: (u32, u32) = (32, 32);
+
+/// Frame sizes every image keeps the converted frames for.
+const RENDITIONS_LIMIT: usize = 4;
+
+/// Everything an image keeps besides its widget, alike for every backend: the content and how it is drawn,
+/// the `N` frames converted for the toolkit, the background loading, the animation and the live frames.
+pub struct State<N> {
+    pub style: Style,
+    pub constraints: SizeConstraints,
+    pub renderer: Renderer,
+    pub renditions: Renditions<N>,
+    pub loader: Loader,
+    pub player: Player,
+    pub sink: Option<sink::Receiver>,
+}
+
+impl<N> State<N> {
+    pub fn new(source: Option<ImageSource>) -> State<N> {
+        State {
+            style: Style::default(),
+            constraints: SizeConstraints::default(),
+            player: Player::new(source.as_ref()),
+            renderer: Renderer::new(source),
+            renditions: Renditions::new(),
+            loader: Loader::new(),
+            sink: None,
+        }
+    }
+    /// Shows `content` from the first frame on, cancelling the loading if there is one.
+    pub fn set_content(&mut self, content: Option<ImageSource>) {
+        self.loader.cancel();
+        self.renderer.set_content(content);
+        self.player.reset(self.renderer.content());
+    }
+    /// Native frame of `size`, rendered and made by `convert` if it is not kept yet. `None` if it is the frame shown already.
+    pub fn frame<F: FnOnce(ImageSource) -> N>(&mut self, size: (u32, u32), convert: F) -> Option<&N> {
+        let (renderer, style) = (&mut self.renderer, &self.style);
+        self.renditions.update(size, || convert(renderer.render(style, size.0, size.1)))
+    }
+}
+
+/// Composes the frames the backends put on screen, so every backend shows the same pixels for the same `Style`.
+pub struct Renderer {
+    content: Option<ImageSource>,
+    frame: usize,
+    region: Option<Region>,
+    placeholder: Option<Placeholder>,
+    saliency: Option<Saliency>,
+    prepared: Option<Prepared>,
+    scale_factor: f32,
+    auto_orientation: bool,
+    rotation: f32,
+    flip: Flip,
+    /// Vector content shown last, parsed on this thread.
+    #[cfg(feature = "svg")]
+    tree: Option<(Arc<Vector>, Tree)>,
+}
+
+/// What `Renderer::shown` made for the device `style`, kept until the content, its part shown or the way it is turned changes,
+/// with its premultiplied copy, made the first time it is filtered.
+struct Prepared {
+    style: Style,
+    shown: ImageSource,
+    premultiplied: Option<RgbaImage>,
+}
+
+impl Renderer {
+    pub fn new(content: Option<ImageSource>) -> Renderer {
+        Renderer {
+            content: content,
+            frame: 0,
+            region: None,
+            placeholder: None,
+            saliency: None,
+            prepared: None,
+            scale_factor: 1.0,
+            auto_orientation: true,
+            rotation: 0.0,
+            flip: Flip::None,
+            #[cfg(feature = "svg")]
+            tree: None,
+        }
+    }
+    pub fn content(&self) -> Option<&ImageSource> {
+        self.content.as_ref()
+    }
+    /// Returns the previous content.
+    pub fn set_content(&mut self, content: Option<ImageSource>) -> Option<ImageSource> {
+        self.frame = 0;
+        self.saliency = None;
+        self.prepared = None;
+        #[cfg(feature = "svg")]
+        {
+            self.tree = None;
+        }
+        mem::replace(&mut self.content, content)
+    }
+    /// Frame of animated content to draw from now on.
+    pub fn set_frame(&mut self, frame: usize) {
+        self.frame = frame;
+        self.prepared = None;
+    }
+    pub fn region(&self) -> Option<Region> {
+        self.region
+    }
+    /// Part of the content drawn instead of the whole content, kept when the content changes.
+    pub fn set_region(&mut self, region: Option<Region>) {
+        self.region = region;
+        self.saliency = None;
+        self.prepared = None;
+    }
+    pub fn placeholder(&self) -> Option<&Placeholder> {
+        self.placeholder.as_ref()
+    }
+    pub fn set_placeholder(&mut self, placeholder: Option<Placeholder>) {
+        self.placeholder = placeholder;
+    }
+    /// Device pixels per logical pixel of the screen the frames are for, 1 by default.
+    pub fn scale_factor(&self) -> f32 {
+        self.scale_factor
+    }
+    /// The frames are rendered in device pixels from then on, the content size stays in logical pixels.
+    pub fn set_scale_factor(&mut self, scale_factor: f32) {
+        self.scale_factor = scale_factor;
+        self.saliency = None;
+        self.prepared = None;
+    }
+    pub fn auto_orientation(&self) -> bool {
+        self.auto_orientation
+    }
+    /// Whether the content is turned upright according to the orientation it is stored in.
+    pub fn set_auto_orientation(&mut self, enabled: bool) {
+        self.auto_orientation = enabled;
+        self.saliency = None;
+        self.prepared = None;
+    }
+    pub fn rotation(&self) -> f32 {
+        self.rotation
+    }
+    /// Clockwise angle the content is drawn at, in degrees, kept within 0..360.
+    pub fn set_rotation(&mut self, degrees: f32) {
+        self.rotation = transform::normalize(degrees);
+        self.saliency = None;
+        self.prepared = None;
+    }
+    pub fn flip(&self) -> Flip {
+        self.flip
+    }
+    pub fn set_flip(&mut self, flip: Flip) {
+        self.flip = flip;
+        self.saliency = None;
+        self.prepared = None;
+    }
+    /// How `content` is turned before being placed: upright, then flipped, then rotated.
+    fn transform(&self, content: &ImageSource) -> Transform {
+        let upright = if self.auto_orientation { Transform::orientation(content.orientation()) } else { Transform::IDENTITY };
+        upright.then(Transform::flip(self.flip)).then(Transform::rotation(self.rotation))
+    }
+    /// Size the content would like to be shown at, before any scaling, in logical pixels.
+    /// While there is no content, it is the size of the placeholder image, or `EMPTY_SIZE`.
+    pub fn natural_size(&self) -> (u32, u32) {
+        match (self.content_size(), &self.placeholder) {
+            (Some(size), _) => size,
+            (None, &Some(Placeholder::Image(ref placeholder))) => placeholder.dimensions(),
+            _ => EMPTY_SIZE,
+        }
+    }
+    /// Size of the bounds of the content part being drawn once turned, in logical pixels.
+    fn content_size(&self) -> Option<(u32, u32)> {
+        self.content.as_ref().map(|content| {
+            let (width, height) = match self.region {
+                Some(Region { trim: Some(trim), .. }) => (trim.width, trim.height),
+                Some(region) => {
+                    let region = clip(region, content.dimensions());
+                    (region.width, region.height)
+                }
+                None => content.dimensions(),
+            };
+            self.transform(content).size((logical(width, content.density()), logical(height, content.density())))
+        })
+    }
+    /// The resolution variant of the content fitting the screen, the part of it being drawn,
+    /// and the amount of device pixels each of its pixels takes when drawn at 1:1.
+    fn variant(&self) -> Option<(&ImageSource, Region, f32)> {
+        let content = self.content.as_ref()?;
+        let variant = content.variant(self.scale_factor);
+        let (width, height) = variant.dimensions();
+        let region = match self.region {
+            // the region is given in pixels of the content itself
+            Some(region) if variant.density() != content.density() => {
+                let ratio = variant.density() / content.density();
+                let scale = |length: u32| (length as f32 * ratio).round() as u32;
+                let scaled = Region::new(scale(region.x), scale(region.y), scale(region.width), scale(region.height));
+                let scaled = match region.trim {
+                    Some(trim) => scaled.with_trim(Trim { x: scale(trim.x), y: scale(trim.y), width: scale(trim.width), height: scale(trim.height) }),
+                    None => scaled,
+                };
+                clip(scaled, (width, height))
+            }
+            Some(region) => clip(region, (width, height)),
+            None => Region::new(0, 0, width, height),
+        };
+        Some((variant, region, self.scale_factor / variant.density()))
+    }
+    /// The current frame of the content, cut down to the region, turned, and scaled to its size on the screen
+    /// if `style` draws it at that size.
+    fn shown(&self, style: &Style) -> Option<ImageSource> {
+        let (variant, region, content_scale) = self.variant()?;
+        let frame = variant.frame(self.frame).unwrap_or_else(|| variant.clone());
+        let (width, height) = frame.dimensions();
+        let shown = match clip(region, (width, height)) {
+            Region { trim: Some(trim), x, y, width, height } => ImageSource::new(untrim(&sub_image(frame.pixels(), x, y, width, height), trim)),
+            region if region != Region::new(0, 0, width, height) => ImageSource::new(sub_image(frame.pixels(), region.x, region.y, region.width, region.height)),
+            _ => frame,
+        };
+        let transform = self.transform(variant);
+        let shown = if transform.is_identity() { shown } else { ImageSource::new(transform.apply(shown.pixels(), style.interpolation)) };
+        if keeps_size(style) && content_scale != 1.0 {
+            return Some(ImageSource::new(prescale(shown.pixels(), content_scale, style)));
+        }
+        Some(shown)
+    }
+    /// `style` with the lengths it gives in logical pixels in device pixels.
+    fn device_style(&self, style: &Style) -> Style {
+        let mut style = *style;
+        if self.scale_factor != 1.0 {
+            let device = |length: f32| (length * self.scale_factor).round();
+            if let Some(ref mut nine_patch) = style.nine_patch {
+                nine_patch.left = device(nine_patch.left as f32) as u32;
+                nine_patch.top = device(nine_patch.top as f32) as u32;
+                nine_patch.right = device(nine_patch.right as f32) as u32;
+                nine_patch.bottom = device(nine_patch.bottom as f32) as u32;
+            }
+            if let ScalePolicy::Tile { ref mut offset, .. } = style.scale {
+                *offset = (device(offset.0 as f32) as i32, device(offset.1 as f32) as i32);
+            }
+        }
+        style
+    }
+    /// Draws the content, or the placeholder while there is none, into a transparent `width` x `height` frame of device pixels.
+    /// A frame showing the whole content at 1:1 shares the content buffer instead of copying it.
+    pub fn render(&mut self, style: &Style, width: u32, height: u32) -> ImageSource {
+        if width < 1 || height < 1 {
+            return ImageSource::new(RgbaImage::new(width, height));
+        }
+        let style = &self.device_style(style);
+        if let Some(mut prepared) = self.prepare(style) {
+            let frame = self.draw(&mut prepared, width, height);
+            self.prepared = Some(prepared);
+            return frame;
+        }
+        let frame = match self.placeholder {
+            Some(Placeholder::Color(color)) => RgbaImage::from_pixel(width, height, color),
+            Some(Placeholder::Image(ref placeholder)) if keeps_size(style) && self.scale_factor != 1.0 => {
+                compose(&prescale(placeholder, self.scale_factor, style), &mut None, style, style.focal_point, width, height)
+            }
+            Some(Placeholder::Image(ref placeholder)) => compose(placeholder, &mut None, style, style.focal_point, width, height),
+            None => RgbaImage::new(width, height),
+        };
+        ImageSource::new(frame)
+    }
+    /// The `rect` part of the frame `render` draws, composing only that part. `rect` must lie within the frame.
+    pub fn render_rect(&mut self, style: &Style, width: u32, height: u32, rect: Rect) -> RgbaImage {
+        let device_style = &self.device_style(style);
+        if let Some(mut prepared) = self.prepare(device_style) {
+            let part = self.draw_rect(&mut prepared, width, height, rect);
+            self.prepared = Some(prepared);
+            return part;
+        }
+        // the placeholder is only drawn until the content is there, there is no part of it to redraw
+        let frame = self.render(style, width, height);
+        sub_image(frame.pixels(), rect.x as u32, rect.y as u32, rect.width as u32, rect.height as u32)
+    }
+    /// The content prepared for `style`, prepared again if it was for another style. Taken out of the renderer while
+    /// drawing, to be put back after.
+    fn prepare(&mut self, style: &Style) -> Option<Prepared> {
+        match self.prepared.take() {
+            Some(prepared) if prepared.style == *style => Some(prepared),
+            _ => self.shown(style).map(|shown| Prepared { style: *style, shown, premultiplied: None }),
+        }
+    }
+    /// Draws the `prepared` content into a `width` x `height` frame.
+    fn draw(&mut self, prepared: &mut Prepared, width: u32, height: u32) -> ImageSource {
+        if is_unscaled(&prepared.style, prepared.shown.dimensions(), width, height) {
+            return prepared.shown.clone();
+        }
+        ImageSource::new(self.draw_rect(prepared, width, height, Rect::new(0, 0, width as i32, height as i32)))
+    }
+    /// Draws the `rect` part of the frame `draw` draws.
+    fn draw_rect(&mut self, prepared: &mut Prepared, width: u32, height: u32, rect: Rect) -> RgbaImage {
+        let (shown, style) = (&prepared.shown, &prepared.style);
+        if is_unscaled(style, shown.dimensions(), width, height) {
+            return sub_image(shown.pixels(), rect.x as u32, rect.y as u32, rect.width as u32, rect.height as u32);
+        }
+        let focal_point = if style.scale == ScalePolicy::CropAuto && style.nine_patch.is_none() {
+            let window = geometry::crop_size(shown.dimensions(), (width as i32, height as i32));
+            self.saliency.get_or_insert_with(|| Saliency::new(shown.pixels())).focal_point(window)
+        } else {
+            style.focal_point
+        };
+        #[cfg(feature = "svg")]
+        {
+            // the picture is drawn whole, vector content being turned into pixels once they are written to
+            if let Some(frame) = self.rasterize(style, focal_point, width, height) {
+                return sub_image(&frame, rect.x as u32, rect.y as u32, rect.width as u32, rect.height as u32);
+            }
+        }
+        compose_rect(shown.pixels(), &mut prepared.premultiplied, style, focal_point, width, height, rect)
+    }
+    /// Draws vector content straight at the frame size, for the scale policies that only scale and move the content.
+    /// `None` if the content is not vector, or is tiled or nine-patched, which is done with its raster.
+    #[cfg(feature = "svg")]
+    fn rasterize(&mut self, style: &Style, focal_point: (f32, f32), width: u32, height: u32) -> Option<RgbaImage> {
+        if style.nine_patch.is_some() {
+            return None;
+        }
+        if let ScalePolicy::Tile { .. } = style.scale {
+            return None;
+        }
+        let vector = self.variant()?.0.vector()?.clone();
+        // parsed once for every content shown, the document cannot be shared with the thread that decoded it
+        if !self.tree.as_ref().map_or(false, |&(ref parsed, _)| Arc::ptr_eq(parsed, &vector)) {
+            self.tree = vector.tree().map(|tree| (vector, tree));
+        }
+        let tree = &self.tree.as_ref()?.1;
+        let (variant, shown, content_scale) = self.variant()?;
+        let mut frame = RgbaImage::new(width, height);
+        let transform = self.transform(variant);
+        let bounds = transform.size((shown.width, shown.height));
+        if bounds.0 < 1 || bounds.1 < 1 {
+            return Some(frame);
+        }
+        // content drawn at 1:1 is drawn at its size on the screen
+        let size = if keeps_size(style) { prescaled_size(bounds, content_scale) } else { bounds };
+        let placement = geometry::place(style.scale, style.gravity, focal_point, size, (width as i32, height as i32));
+        if !placement.is_empty() {
+            let (src, dst) = (placement.src, placement.dst);
+            let (placed_x, placed_y) = (placement.scale_x(), placement.scale_y());
+            let scale = (size.0 as f64 / bounds.0 as f64 * placed_x, size.1 as f64 / bounds.1 as f64 * placed_y);
+            // the picture is turned within the bounds, scaled with them, and cut down to the part placed
+            let m = transform.matrix();
+            let turned = transform.map_point((-(shown.x as f64), -(shown.y as f64)), (shown.width, shown.height));
+            let matrix = [m[0] * scale.0, m[1] * scale.0, m[2] * scale.1, m[3] * scale.1];
+            let offset = (turned.0 * scale.0 - src.x as f64 * placed_x, turned.1 * scale.1 - src.y as f64 * placed_y);
+            let part = tree.rasterize(matrix, offset, dst.width as u32, dst.height as u32);
+            frame.copy_from(&part, dst.x as u32, dst.y as u32);
+        }
+        Some(frame)
+    }
+    /// Writes `patch` over the content at (`x`, `y`), cut down to what lies within the content.
+    /// Returns the part of the content changed, `None` if there is no still content to write to.
+    /// Vector content turns into its raster, which changes all of it once scaled.
+    /// The detail map `ScalePolicy::CropAuto` keeps the crop window by is not updated, small changes hardly move it.
+    pub fn update_pixels(&mut self, x: u32, y: u32, patch: &RgbaImage) -> Option<Region> {
+        self.prepared = None;
+        let was_vector = self.content.as_ref().map_or(false, ImageSource::is_vector);
+        let pixels = self.content.as_mut().and_then(ImageSource::pixels_mut)?;
+        let (width, height) = pixels.dimensions();
+        let (patch_width, patch_height) = patch.dimensions();
+        let changed = clip(Region::new(x, y, patch_width, patch_height), (width, height));
+        let (pixels, patch): (&mut [u8], &[u8]) = (pixels, patch);
+        let row = changed.width as usize * 4;
+        for py in 0..changed.height {
+            let src = py as usize * patch_width as usize * 4;
+            let dst = ((changed.y + py) as usize * width as usize + changed.x as usize) * 4;
+            pixels[dst..dst + row].copy_from_slice(&patch[src..src + row]);
+        }
+        if was_vector {
+            Some(Region::new(0, 0, width, height))
+        } else {
+            Some(changed)
+        }
+    }
+    /// Part of a `width` x `height` frame of `style` showing the `changed` part of the content, `None` if it is not shown at all.
+    pub fn damage(&self, style: &Style, changed: Region, width: u32, height: u32) -> Option<Rect> {
+        let style = &self.device_style(style);
+        let (variant, shown, content_scale) = self.variant()?;
+        let changed = Rect::new(changed.x as i32 - shown.x as i32, changed.y as i32 - shown.y as i32, changed.width as i32, changed.height as i32);
+        let mut changed = changed.intersect(&Rect::new(0, 0, shown.width as i32, shown.height as i32));
+        let mut size = (shown.width, shown.height);
+        if let Some(trim) = shown.trim {
+            changed = Rect::new(changed.x + trim.x as i32, changed.y + trim.y as i32, changed.width, changed.height).intersect(&Rect::new(0, 0, trim.width as i32, trim.height as i32));
+            size = (trim.width, trim.height);
+        }
+        let frame = Rect::new(0, 0, width as i32, height as i32);
+        if changed.is_empty() || frame.is_empty() {
+            return None;
+        }
+        let transform = self.transform(variant);
+        if !transform.is_identity() {
+            changed = match transform.map_rect(changed, size) {
+                Some(changed) => changed,
+                // resampled at an angle, every frame pixel may blend in the changed ones
+                None => return Some(frame),
+            };
+            size = transform.size(size);
+        }
+        if keeps_size(style) && content_scale != 1.0 {
+            let scaled = prescaled_size(size, content_scale);
+            let support = if style.scale == ScalePolicy::FitInteger { 0.0 } else { support(style.interpolation) };
+            changed = scale_damage(changed, Rect::new(0, 0, size.0 as i32, size.1 as i32), Rect::new(0, 0, scaled.0 as i32, scaled.1 as i32), support);
+            size = scaled;
+        }
+        if is_unscaled(style, size, width, height) {
+            return Some(changed);
+        }
+        match style.scale {
+            // every content pixel is drawn many times, or the crop window may move
+            ScalePolicy::Tile { .. } | ScalePolicy::CropAuto => return Some(frame),
+            _ if style.nine_patch.is_some() => return Some(frame),
+            _ => {}
+        }
+        let placement = geometry::place(style.scale, style.gravity, style.focal_point, size, (width as i32, height as i32));
+        if placement.is_empty() {
+            return None;
+        }
+        let (src, dst) = (placement.src, placement.dst);
+        let support = if style.scale == ScalePolicy::FitInteger || (src.width == dst.width && src.height == dst.height) {
+            0.0
+        } else {
+            support(style.interpolation)
+        };
+        let damage = scale_damage(changed, src, dst, support).intersect(&frame);
+        if damage.is_empty() {
+            None
+        } else {
+            Some(damage)
+        }
+    }
+    pub fn integer_scale(&self, style: &Style, width: u32, height: u32) -> Option<u32> {
+        match self.content_size() {
+            Some(size) if style.scale == ScalePolicy::FitInteger && style.nine_patch.is_none() => Some(geometry::integer_factor(size, (width as i32, height as i32))),
+            _ => None,
+        }
+    }
+}
+
+/// `region` cut down to what lies within the content of `size`.
+fn clip(region: Region, size: (u32, u32)) -> Region {
+    let x = cmp::min(region.x, size.0);
+    let y = cmp::min(region.y, size.1);
+    Region { trim: region.trim, ..Region::new(x, y, cmp::min(region.width, size.0 - x), cmp::min(region.height, size.1 - y)) }
+}
+
+/// Whether a `width` x `height` frame of `style` is exactly the content of `content` size.
+fn is_unscaled(style: &Style, content: (u32, u32), width: u32, height: u32) -> bool {
+    if content != (width, height) {
+        return false;
+    }
+    match style.scale {
+        ScalePolicy::Tile { offset, .. } => style.nine_patch.is_some() || offset == (0, 0),
+        _ => true,
+    }
+}
+
+/// Whether `style` draws the content at a scale depending on the content size, rather than scaling it to the area.
+fn keeps_size(style: &Style) -> bool {
+    match style.scale {
+        _ if style.nine_patch.is_some() => true,
+        ScalePolicy::None | ScalePolicy::FitInteger | ScalePolicy::Tile { .. } => true,
+        _ => false,
+    }
+}
+
+fn prescaled_size(size: (u32, u32), scale: f32) -> (u32, u32) {
+    let scale = |length: u32| cmp::max(1, (length as f32 * scale).round() as u32);
+    (scale(size.0), scale(size.1))
+}
+
+/// `content` scaled by `scale`, so `style` draws it at its size on the screen.
+fn prescale(content: &RgbaImage, scale: f32, style: &Style) -> RgbaImage {
+    let (width, height) = prescaled_size(content.dimensions(), scale);
+    // pixel art stays crisp
+    let interpolation = if style.scale == ScalePolicy::FitInteger { Interpolation::Nearest } else { style.interpolation };
+    resize(content, interpolation, width, height)
+}
+
+/// How many source pixels around the one sampled the filter of `interpolation` blends in.
+fn support(interpolation: Interpolation) -> f64 {
+    match interpolation {
+        Interpolation::Nearest => 0.0,
+        Interpolation::Bilinear => 1.0,
+        Interpolation::Bicubic => 2.0,
+        Interpolation::Lanczos => 3.0,
+    }
+}
+
+/// Part of `to` drawn from the `rect` part of `from` when `from` is scaled onto `to` by a filter of `support`,
+/// which reaches further when scaling down.
+fn scale_damage(rect: Rect, from: Rect, to: Rect, support: f64) -> Rect {
+    let (scale_x, scale_y) = (to.width as f64 / from.width as f64, to.height as f64 / from.height as f64);
+    let reach_x = (support / scale_x.min(1.0)).ceil() as i32;
+    let reach_y = (support / scale_y.min(1.0)).ceil() as i32;
+    let rect = Rect::new(rect.x - reach_x, rect.y - reach_y, rect.width + reach_x * 2, rect.height + reach_y * 2).intersect(&from);
+    if rect.is_empty() {
+        return rect;
+    }
+    let left = to.x + ((rect.x - from.x) as f64 * scale_x).floor() as i32;
+    let top = to.y + ((rect.y - from.y) as f64 * scale_y).floor() as i32;
+    let right = to.x + ((rect.x + rect.width - from.x) as f64 * scale_x).ceil() as i32;
+    let bottom = to.y + ((rect.y + rect.height - from.y) as f64 * scale_y).ceil() as i32;
+    Rect::new(left, top, right - left, bottom - top).intersect(&to)
+}
+
+/// Draws `content` into a transparent `width` x `height` frame according to `style`, `focal_point` replacing the one of `style`.
+/// `premultiplied` keeps the premultiplied copy of `content` for the next frames, once it is made.
+fn compose(content: &RgbaImage, premultiplied: &mut Option<RgbaImage>, style: &Style, focal_point: (f32, f32), width: u32, height: u32) -> RgbaImage {
+    compose_rect(content, premultiplied, style, focal_point, width, height, Rect::new(0, 0, width as i32, height as i32))
+}
+
+/// The `rect` part of the frame `compose` draws, composing only that part.
+fn compose_rect(content: &RgbaImage, premultiplied: &mut Option<RgbaImage>, style: &Style, focal_point: (f32, f32), width: u32, height: u32, rect: Rect) -> RgbaImage {
+    if let Some(nine_patch) = style.nine_patch {
+        return nine_patch_rect(content, premultiplied, nine_patch, style.interpolation, width, height, rect);
+    }
+    if let ScalePolicy::Tile { mode, offset } = style.scale {
+        return tile(content, mode, (offset.0 - rect.x, offset.1 - rect.y), rect.width as u32, rect.height as u32);
+    }
+
+    let area = (width as i32, height as i32);
+    let placement = geometry::place(style.scale, style.gravity, focal_point, content.dimensions(), area);
+    let mut frame = RgbaImage::new(rect.width as u32, rect.height as u32);
+    let window = placement.dst.intersect(&rect);
+    if !placement.is_empty() && !window.is_empty() {
+        let (src, dst) = (placement.src, placement.dst);
+        // the window within the placed content
+        let (x, y) = ((window.x - dst.x) as u32, (window.y - dst.y) as u32);
+        let part = if src.width == dst.width && src.height == dst.height {
+            sub_image(content, src.x as u32 + x, src.y as u32 + y, window.width as u32, window.height as u32)
+        } else if style.scale == ScalePolicy::FitInteger {
+            let factor = (dst.width / src.width) as u32;
+            RgbaImage::from_fn(window.width as u32, window.height as u32, |px, py| *content.get_pixel(src.x as u32 + (x + px) / factor, src.y as u32 + (y + py) / factor))
+        } else {
+            let within = Rect::new(x as i32, y as i32, window.width, window.height);
+            resize_part(content, premultiplied, src, style.interpolation, dst.width as u32, dst.height as u32, within)
+        };
+        frame.copy_from(&part, (window.x - rect.x) as u32, (window.y - rect.y) as u32);
+    }
+    frame
+}
+
+/// Scales `content` to `width` x `height`, filtering premultiplied pixels, so the color of the transparent ones does not
+/// bleed into the edges of the opaque ones.
+pub fn resize(content: &RgbaImage, interpolation: Interpolation, width: u32, height: u32) -> RgbaImage {
+    let (cw, ch) = content.dimensions();
+    resize_part(content, &mut None, Rect::new(0, 0, cw as i32, ch as i32), interpolation, width, height, Rect::new(0, 0, width as i32, height as i32))
+}
+
+/// The `window` part of what `resize` makes of the `part` of `content`, taking the premultiplied pixels from
+/// `premultiplied`, or keeping them there for the next time if they are not made yet.
+fn resize_part(content: &RgbaImage, premultiplied: &mut Option<RgbaImage>, part: Rect, interpolation: Interpolation, width: u32, height: u32, window: Rect) -> RgbaImage {
+    if interpolation == Interpolation::Nearest {
+        return resample(content, part, interpolation, width, height, window);
+    }
+    let premultiplied = premultiplied.get_or_insert_with(|| {
+        let mut premultiplied = content.clone();
+        pixels::premultiply(&mut premultiplied);
+        premultiplied
+    });
+    let mut frame = resample(premultiplied, part, interpolation, width, height, window);
+    pixels::unpremultiply(&mut frame);
+    frame
+}
+
+/// Fills a `width` x `height` frame with copies of `content`, the first copy starting at `offset`.
+pub fn tile(content: &RgbaImage, mode: TileMode, offset: (i32, i32), width: u32, height: u32) -> RgbaImage {
+    let (cw, ch) = content.dimensions();
+    if cw < 1 || ch < 1 {
+        return RgbaImage::new(width, height);
+    }
+    let columns = (0..width as i32).map(|x| tile_coord(x - offset.0, cw as i32, mode)).collect::<Vec<_>>();
+    let mut frame = RgbaImage::new(width, height);
+    for y in 0..height {
+        let sy = tile_coord(y as i32 - offset.1, ch as i32, mode);
+        for x in 0..width {
+            frame.put_pixel(x, y, *content.get_pixel(columns[x as usize], sy));
+        }
+    }
+    frame
+}
+
+/// Source coordinate for the frame coordinate `pos` along an axis of `len` content pixels.
+fn tile_coord(pos: i32, len: i32, mode: TileMode) -> u32 {
+    let coord = match mode {
+        TileMode::Repeat => modulo(pos, len),
+        TileMode::MirroredRepeat => {
+            let m = modulo(pos, len * 2);
+            if m < len {
+                m
+            } else {
+                len * 2 - 1 - m
+            }
+        }
+        TileMode::ClampToEdge => pos.max(0).min(len - 1),
+    };
+    coord as u32
+}
+
+fn modulo(a: i32, b: i32) -> i32 {
+    ((a % b) + b) % b
+}
+
+/// Composes a `width` x `height` frame keeping the corners outside of the `nine_patch` insets at 1:1.
+/// `premultiplied` is taken or kept as `resize_part` does.
+pub fn nine_patch(content: &RgbaImage, premultiplied: &mut Option<RgbaImage>, nine_patch: NinePatch, interpolation: Interpolation, width: u32, height: u32) -> RgbaImage {
+    nine_patch_rect(content, premultiplied, nine_patch, interpolation, width, height, Rect::new(0, 0, width as i32, height as i32))
+}
+
+/// The `rect` part of the frame `nine_patch` composes, composing only the parts of the patches within it.
+fn nine_patch_rect(content: &RgbaImage, premultiplied: &mut Option<RgbaImage>, nine_patch: NinePatch, interpolation: Interpolation, width: u32, height: u32, rect: Rect) -> RgbaImage {
+    let (cw, ch) = content.dimensions();
+    let mut frame = RgbaImage::new(rect.width as u32, rect.height as u32);
+
+    // Corners never grow, but shrink proportionally if the frame is smaller than the sum of the insets.
+    let (l, r) = shrink(nine_patch.left.min(cw), nine_patch.right.min(cw), width);
+    let (t, b) = shrink(nine_patch.top.min(ch), nine_patch.bottom.min(ch), height);
+    let src_cols = [(0, l), (l, cw.saturating_sub(l + r)), (cw - r, r)];
+    let src_rows = [(0, t), (t, ch.saturating_sub(t + b)), (ch - b, b)];
+    let dst_cols = [(0, l), (l, width - l - r), (width - r, r)];
+    let dst_rows = [(0, t), (t, height - t - b), (height - b, b)];
+
+    for row in 0..3 {
+        for col in 0..3 {
+            let (sx, sw) = src_cols[col];
+            let (sy, sh) = src_rows[row];
+            let (dx, dw) = dst_cols[col];
+            let (dy, dh) = dst_rows[row];
+            if sw < 1 || sh < 1 || dw < 1 || dh < 1 {
+                continue;
+            }
+            let window = Rect::new(dx as i32, dy as i32, dw as i32, dh as i32).intersect(&rect);
+            if window.is_empty() {
+                continue;
+            }
+            // the window within the patch
+            let (x, y, ww, wh) = (window.x as u32 - dx, window.y as u32 - dy, window.width as u32, window.height as u32);
+            let patch = match nine_patch.fill {
+                _ if sw == dw && sh == dh => sub_image(content, sx + x, sy + y, ww, wh),
+                NinePatchFill::Stretch => {
+                    let within = Rect::new(x as i32, y as i32, ww as i32, wh as i32);
+                    resize_part(content, premultiplied, Rect::new(sx as i32, sy as i32, sw as i32, sh as i32), interpolation, dw, dh, within)
+                }
+                NinePatchFill::Tile => tile(&sub_image(content, sx, sy, sw, sh), TileMode::Repeat, (-(x as i32), -(y as i32)), ww, wh),
+            };
+            frame.copy_from(&patch, (window.x - rect.x) as u32, (window.y - rect.y) as u32);
+        }
+    }
+    frame
+}
+
+/// Copies the `width` x `height` region at (`x`, `y`) out of `content`.
+pub fn sub_image(content: &RgbaImage, x: u32, y: u32, width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |px, py| *content.get_pixel(x + px, y + py))
+}
+
+/// `region` put back into the transparent sprite it was trimmed from.
+fn untrim(region: &RgbaImage, trim: Trim) -> RgbaImage {
+    let mut sprite = RgbaImage::new(trim.width, trim.height);
+    for (x, y, &pixel) in region.enumerate_pixels() {
+        let (x, y) = (trim.x + x, trim.y + y);
+        if x < trim.width && y < trim.height {
+            sprite.put_pixel(x, y, pixel);
+        }
+    }
+    sprite
+}
+
+fn shrink(start: u32, end: u32, available: u32) -> (u32, u32) {
+    if start + end <= available {
+        (start, end)
+    } else {
+        let start = (start as u64 * available as u64 / (start + end) as u64) as u32;
+        (start, available - start)
+    }
+}
+
+/// Splits an Android-style `.9.png` into its content and the insets marked with opaque black on its top row and left column.
+pub fn parse_nine_png(content: &RgbaImage) -> Option<(NinePatch, RgbaImage)> {
+    let (w, h) = content.dimensions();
+    if w < 3 || h < 3 {
+        return None;
+    }
+    let is_marker = |x, y| content.get_pixel(x, y).data == [0, 0, 0, 255];
+    let columns = (1..w - 1).filter(|&x| is_marker(x, 0)).collect::<Vec<_>>();
+    let rows = (1..h - 1).filter(|&y| is_marker(0, y)).collect::<Vec<_>>();
+    if columns.is_empty() || rows.is_empty() {
+        return None;
+    }
+    let nine_patch = NinePatch {
+        left: columns[0] - 1,
+        top: rows[0] - 1,
+        right: w - 2 - columns[columns.len() - 1],
+        bottom: h - 2 - rows[rows.len() - 1],
+        fill: NinePatchFill::Stretch,
+    };
+    Some((nine_patch, sub_image(content, 1, 1, w - 2, h - 2)))
+}
+
+/// Frames rendered and converted for the toolkit, kept per frame size, so a size shown recently is not rendered again.
+pub struct Renditions<N> {
+    // least recently used first
+    items: Vec<((u32, u32), N)>,
+    shown: Option<(u32, u32)>,
+}
+
+impl<N> Renditions<N> {
+    pub fn new() -> Renditions<N> {
+        Renditions { items: Vec::new(), shown: None }
+    }
+    /// Native frame for `size`, made by `convert` if it is not kept yet. `None` if it is the frame shown already.
+    pub fn update<F: FnOnce() -> N>(&mut self, size: (u32, u32), convert: F) -> Option<&N> {
+        if self.shown == Some(size) {
+            return None;
+        }
+        let item = match self.items.iter().position(|&(item_size, _)| item_size == size) {
+            Some(index) => self.items.remove(index),
+            None => (size, convert()),
+        };
+        if self.items.len() >= RENDITIONS_LIMIT {
+            self.items.remove(0);
+        }
+        self.items.push(item);
+        self.shown = Some(size);
+        self.items.last().map(|&(_, ref native)| native)
+    }
+    /// Frame passed to the toolkit by the last `update`.
+    pub fn shown(&self) -> Option<&N> {
+        match self.shown {
+            Some(_) => self.items.last().map(|&(_, ref native)| native),
+            None => None,
+        }
+    }
+    /// Frame shown at `size`, to be redrawn in place when the content changes but not its size.
+    /// All the other frames are dropped, being out of date, and so is the shown one if it is of another size.
+    pub fn reuse(&mut self, size: (u32, u32)) -> Option<&mut N> {
+        if self.shown != Some(size) {
+            self.clear();
+            return None;
+        }
+        let shown = self.items.pop();
+        self.items.clear();
+        self.items.extend(shown);
+        self.items.last_mut().map(|&mut (_, ref mut native)| native)
+    }
+    /// Drops all the frames, to be called whenever the content or the style changes.
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.shown = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::Rgba;
+
+    /// Opaque red square within transparent green, the color a straight alpha filter would bleed into the edges.
+    fn sprite() -> RgbaImage {
+        RgbaImage::from_fn(8, 8, |x, y| match (x, y) {
+            (2...5, 2...5) => Rgba { data: [255, 0, 0, 255] },
+            _ => Rgba { data: [0, 255, 0, 0] },
+        })
+    }
+
+    fn assert_no_fringe(frame: &RgbaImage) {
+        for pixel in frame.pixels() {
+            if pixel.data[3] > 0 {
+                assert_eq!((pixel.data[0], pixel.data[1], pixel.data[2]), (255, 0, 0), "{:?}", pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn no_fringe() {
+        for &interpolation in &[Interpolation::Bilinear, Interpolation::Bicubic, Interpolation::Lanczos] {
+            for &size in &[5, 13, 32] {
+                let frame = resize(&sprite(), interpolation, size, size);
+                assert!(frame.pixels().any(|pixel| pixel.data[3] > 0 && pixel.data[3] < 255), "{:?} {}", interpolation, size);
+                assert_no_fringe(&frame);
+            }
+        }
+    }
+
+    #[test]
+    fn premultiplied_once() {
+        let mut renderer = Renderer::new(Some(ImageSource::new(sprite())));
+        let style = Style { scale: ScalePolicy::Stretch, ..Style::default() };
+        let first = renderer.render(&style, 20, 20);
+        assert!(renderer.prepared.as_ref().map_or(false, |prepared| prepared.premultiplied.is_some()));
+        assert_no_fringe(first.pixels());
+
+        // the copy made for the first frame is the one the next frames are filtered from
+        let second = renderer.render(&style, 13, 13);
+        assert_no_fringe(second.pixels());
+        assert_eq!(second.pixels().clone().into_raw(), resize(&sprite(), style.interpolation, 13, 13).into_raw());
+
+        // and it is dropped with the pixels it is a copy of
+        renderer.update_pixels(0, 0, &RgbaImage::from_pixel(8, 1, Rgba { data: [0, 0, 255, 255] }));
+        assert!(renderer.prepared.is_none());
+        let third = renderer.render(&style, 13, 13);
+        assert_eq!(third.pixels().get_pixel(6, 0).data, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn trimmed_region() {
+        let mut renderer = Renderer::new(Some(ImageSource::new(sprite())));
+        renderer.set_region(Some(Region::new(2, 2, 4, 4).with_trim(Trim { x: 1, y: 3, width: 6, height: 8 })));
+        assert_eq!(renderer.natural_size(), (6, 8));
+        let frame = renderer.render(&Style::default(), 6, 8);
+        for (x, y, pixel) in frame.pixels().enumerate_pixels() {
+            let inside = x >= 1 && x < 5 && y >= 3 && y < 7;
+            assert_eq!(pixel.data, if inside { [255, 0, 0, 255] } else { [0; 4] }, "{} {}", x, y);
+        }
+        assert_eq!(renderer.damage(&Style::default(), Region::new(2, 2, 1, 1), 6, 8), Some(Rect::new(1, 3, 1, 1)));
+    }
+
+    #[cfg(feature = "svg")]
+    #[test]
+    fn vector_turned() {
+        use super::super::Orientation;
+
+        // left half red, stored turned a quarter counterclockwise, then flipped upside down
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="2"><rect width="2" height="2" fill="red"/></svg>"#;
+        let source = ImageSource::from_svg(svg).unwrap().with_orientation(Orientation::Rotate90);
+        let mut renderer = Renderer::new(Some(source));
+        renderer.set_flip(Flip::Vertical);
+        assert_eq!(renderer.natural_size(), (2, 4));
+        // drawn sharp at both sizes, the red half ending up at the bottom
+        for &scale in &[1, 3] {
+            let frame = renderer.render(&Style::default(), 2 * scale, 4 * scale);
+            for (x, y, pixel) in frame.pixels().enumerate_pixels() {
+                assert_eq!(pixel.data, if y >= 2 * scale { [255, 0, 0, 255] } else { [0; 4] }, "{} at {}x{}", scale, x, y);
+            }
+        }
+        // parsed for the first size and kept for the second one
+        let parsed = renderer.tree.as_ref().map(|&(_, ref tree)| tree as *const Tree);
+        renderer.render(&Style::default(), 4, 8);
+        assert!(parsed.is_some());
+        assert_eq!(renderer.tree.as_ref().map(|&(_, ref tree)| tree as *const Tree), parsed);
+    }
+
+    #[test]
+    fn rect_of_frame() {
+        let tile = ScalePolicy::Tile { mode: TileMode::MirroredRepeat, offset: (3, -2) };
+        let stretch = Some(NinePatch::new(2, 3, 1, 2, NinePatchFill::Stretch));
+        let tiled = Some(NinePatch::new(2, 3, 1, 2, NinePatchFill::Tile));
+        let styles = [
+            Style { scale: ScalePolicy::FitCenter, interpolation: Interpolation::Bicubic, ..Style::default() },
+            Style { scale: ScalePolicy::CropAuto, ..Style::default() },
+            Style { scale: ScalePolicy::FitInteger, ..Style::default() },
+            Style { scale: ScalePolicy::None, gravity: Gravity::BottomRight, ..Style::default() },
+            Style { scale: tile, ..Style::default() },
+            Style { nine_patch: stretch, ..Style::default() },
+            Style { nine_patch: tiled, ..Style::default() },
+        ];
+        let mut renderer = Renderer::new(Some(ImageSource::new(sprite())));
+        for style in &styles {
+            for &(width, height) in &[(21, 13), (5, 7)] {
+                let frame = renderer.render(style, width, height);
+                for &rect in &[Rect::new(0, 0, 1, 1), Rect::new(1, 2, 3, 4), Rect::new(0, 0, width as i32, height as i32)] {
+                    let part = renderer.render_rect(style, width, height, rect);
+                    assert_eq!(part.dimensions(), (rect.width as u32, rect.height as u32));
+                    for (x, y, pixel) in part.enumerate_pixels() {
+                        assert_eq!(pixel, frame.pixels().get_pixel(rect.x as u32 + x, rect.y as u32 + y), "{:?} {}x{} {:?}", style, width, height, rect);
+                    }
+                }
+            }
+        }
+    }
+}
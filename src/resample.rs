@@ -0,0 +1,160 @@
+//! Scaling of the content by the filter of each `Interpolation`, the columns first, then the rows.
+//!
+//! Every pixel of the result only depends on its position, so any window of it can be computed on its own and comes
+//! out the same as that part of the whole result. That is what lets a frame be redrawn only where it changed.
+
+use super::geometry::Rect;
+use super::Interpolation;
+
+use image::{Rgba, RgbaImage};
+
+use std::f32::consts::PI;
+
+/// The `window` part of the `part` of `content` scaled to `width` x `height`. `window` must lie within that size.
+pub fn resample(content: &RgbaImage, part: Rect, interpolation: Interpolation, width: u32, height: u32, window: Rect) -> RgbaImage {
+    let (window_width, window_height) = (window.width as u32, window.height as u32);
+    if part.is_empty() || window_width < 1 || window_height < 1 {
+        return RgbaImage::new(window_width, window_height);
+    }
+    let columns = (window.x..window.x + window.width).map(|x| taps(interpolation, part.width as u32, width, x as u32)).collect::<Vec<_>>();
+    let rows = (window.y..window.y + window.height).map(|y| taps(interpolation, part.height as u32, height, y as u32)).collect::<Vec<_>>();
+
+    // only the source columns the window columns are sampled from are scaled vertically
+    let first = columns.iter().map(|taps| taps.first).min().unwrap_or(0);
+    let last = columns.iter().map(|taps| taps.first + taps.weights.len() as u32).max().unwrap_or(0);
+    let mut scaled_rows = RgbaImage::new(last - first, window_height);
+    for (y, taps) in rows.iter().enumerate() {
+        for x in first..last {
+            let pixel = taps.apply(|i| *content.get_pixel(part.x as u32 + x, part.y as u32 + i));
+            scaled_rows.put_pixel(x - first, y as u32, pixel);
+        }
+    }
+    let mut frame = RgbaImage::new(window_width, window_height);
+    for y in 0..window_height {
+        for (x, taps) in columns.iter().enumerate() {
+            frame.put_pixel(x as u32, y, taps.apply(|i| *scaled_rows.get_pixel(i - first, y)));
+        }
+    }
+    frame
+}
+
+/// The source pixels blended into a result pixel along one axis and their weights, summing up to 1.
+struct Taps {
+    first: u32,
+    weights: Vec<f32>,
+}
+
+impl Taps {
+    fn apply<F: Fn(u32) -> Rgba<u8>>(&self, pixel: F) -> Rgba<u8> {
+        let mut sum = [0.0; 4];
+        for (i, &weight) in self.weights.iter().enumerate() {
+            let pixel = pixel(self.first + i as u32);
+            for channel in 0..4 {
+                sum[channel] += pixel.data[channel] as f32 * weight;
+            }
+        }
+        let channel = |channel: usize| sum[channel].max(0.0).min(255.0).round() as u8;
+        Rgba { data: [channel(0), channel(1), channel(2), channel(3)] }
+    }
+}
+
+/// Taps of the result pixel at `at` of `length` scaled from `source` pixels. Scaling down, the filter is widened to
+/// cover all the source pixels the result pixel stands for.
+fn taps(interpolation: Interpolation, source: u32, length: u32, at: u32) -> Taps {
+    let ratio = source as f32 / length as f32;
+    let widen = ratio.max(1.0);
+    // the center of the result pixel, in source pixels whose centers lie at whole numbers
+    let center = (at as f32 + 0.5) * ratio - 0.5;
+    if interpolation == Interpolation::Nearest {
+        let nearest = (center + 0.5).floor().max(0.0).min(source as f32 - 1.0) as u32;
+        return Taps { first: nearest, weights: vec![1.0] };
+    }
+    let support = support(interpolation) * widen;
+    let first = (center - support).ceil().max(0.0) as u32;
+    let last = ((center + support).floor() as u32).min(source - 1).max(first);
+    let mut weights = (first..last + 1).map(|i| kernel(interpolation, (i as f32 - center) / widen)).collect::<Vec<_>>();
+    let sum: f32 = weights.iter().sum();
+    if sum != 0.0 {
+        for weight in &mut weights {
+            *weight /= sum;
+        }
+    }
+    Taps { first: first, weights: weights }
+}
+
+fn support(interpolation: Interpolation) -> f32 {
+    match interpolation {
+        Interpolation::Nearest => 0.5,
+        Interpolation::Bilinear => 1.0,
+        Interpolation::Bicubic => 2.0,
+        Interpolation::Lanczos => 3.0,
+    }
+}
+
+fn kernel(interpolation: Interpolation, x: f32) -> f32 {
+    let x = x.abs();
+    match interpolation {
+        Interpolation::Nearest => if x <= 0.5 { 1.0 } else { 0.0 },
+        Interpolation::Bilinear => (1.0 - x).max(0.0),
+        // Catmull-Rom
+        Interpolation::Bicubic => {
+            if x < 1.0 {
+                1.5 * x * x * x - 2.5 * x * x + 1.0
+            } else if x < 2.0 {
+                -0.5 * x * x * x + 2.5 * x * x - 4.0 * x + 2.0
+            } else {
+                0.0
+            }
+        }
+        Interpolation::Lanczos => {
+            if x < 3.0 {
+                sinc(x) * sinc(x / 3.0)
+            } else {
+                0.0
+            }
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (x * PI).sin() / (x * PI)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content() -> RgbaImage {
+        RgbaImage::from_fn(13, 9, |x, y| Rgba { data: [(x * 19) as u8, (y * 27) as u8, ((x * y) % 7 * 36) as u8, if (x + y) % 3 == 0 { 0 } else { 255 }] })
+    }
+
+    #[test]
+    fn windows() {
+        let content = content();
+        let part = Rect::new(2, 1, 9, 7);
+        for &interpolation in &[Interpolation::Nearest, Interpolation::Bilinear, Interpolation::Bicubic, Interpolation::Lanczos] {
+            for &(width, height) in &[(30, 20), (4, 3), (9, 14)] {
+                let whole = resample(&content, part, interpolation, width, height, Rect::new(0, 0, width as i32, height as i32));
+                for &window in &[Rect::new(0, 0, 1, 1), Rect::new(1, 2, 3, 1), Rect::new(width as i32 - 2, height as i32 - 2, 2, 2)] {
+                    let part = resample(&content, part, interpolation, width, height, window);
+                    for (x, y, pixel) in part.enumerate_pixels() {
+                        assert_eq!(pixel, whole.get_pixel(window.x as u32 + x, window.y as u32 + y), "{:?} {}x{} {:?}", interpolation, width, height, window);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unscaled() {
+        let content = content();
+        for &interpolation in &[Interpolation::Nearest, Interpolation::Bilinear, Interpolation::Bicubic, Interpolation::Lanczos] {
+            let same = resample(&content, Rect::new(0, 0, 13, 9), interpolation, 13, 9, Rect::new(0, 0, 13, 9));
+            assert_eq!(same.into_raw(), content.clone().into_raw(), "{:?}", interpolation);
+        }
+    }
+}
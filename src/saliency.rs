@@ -0,0 +1,173 @@
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+
+use std::collections::HashMap;
+
+/// Longest side of the downscaled copy the edge density is measured on.
+const SAMPLE_SIZE: u32 = 64;
+/// Crop window sizes remembered before the cache is dropped.
+const CACHE_LIMIT: usize = 32;
+
+/// Edge density map of a content, used by `ScalePolicy::CropAuto` to find the most detailed crop window.
+///
+/// Built once per content, the best window position is then cached per window size.
+pub struct Saliency {
+    content_size: (u32, u32),
+    sample_size: (u32, u32),
+    // summed-area table of the edge magnitudes, (sample width + 1) x (sample height + 1)
+    integral: Vec<f64>,
+    cache: HashMap<(u32, u32), (f32, f32)>,
+}
+
+impl Saliency {
+    pub fn new(content: &RgbaImage) -> Saliency {
+        let content_size = content.dimensions();
+        let rate = (SAMPLE_SIZE as f64 / content_size.0.max(content_size.1).max(1) as f64).min(1.0);
+        let sample = imageops::resize(
+            content,
+            ((content_size.0 as f64 * rate).round() as u32).max(1),
+            ((content_size.1 as f64 * rate).round() as u32).max(1),
+            FilterType::Triangle,
+        );
+        let (sw, sh) = sample.dimensions();
+
+        let luma = sample
+            .pixels()
+            .map(|p| {
+                let [r, g, b, a] = p.data;
+                (0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64) * a as f64 / 255.0
+            })
+            .collect::<Vec<_>>();
+        let at = |x: u32, y: u32| luma[(y * sw + x) as usize];
+
+        let stride = sw as usize + 1;
+        let mut integral = vec![0f64; stride * (sh as usize + 1)];
+        for y in 0..sh {
+            let mut row = 0f64;
+            for x in 0..sw {
+                let dx = if x + 1 < sw { at(x + 1, y) - at(x, y) } else { 0.0 };
+                let dy = if y + 1 < sh { at(x, y + 1) - at(x, y) } else { 0.0 };
+                row += dx.abs() + dy.abs();
+                let i = (y as usize + 1) * stride + x as usize + 1;
+                integral[i] = integral[i - stride] + row;
+            }
+        }
+
+        Saliency {
+            content_size: content_size,
+            sample_size: (sw, sh),
+            integral: integral,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Normalized center of the `window` (in content pixels) covering the most edges.
+    pub fn focal_point(&mut self, window: (u32, u32)) -> (f32, f32) {
+        if let Some(point) = self.cache.get(&window) {
+            return *point;
+        }
+        let point = self.find(window);
+        if self.cache.len() >= CACHE_LIMIT {
+            self.cache.clear();
+        }
+        self.cache.insert(window, point);
+        point
+    }
+
+    fn find(&self, window: (u32, u32)) -> (f32, f32) {
+        let (cw, ch) = self.content_size;
+        let (sw, sh) = self.sample_size;
+        if cw < 1 || ch < 1 || sw < 1 || sh < 1 {
+            return (0.5, 0.5);
+        }
+        let ww = ((window.0 as u64 * sw as u64 + cw as u64 - 1) / cw as u64).max(1).min(sw as u64) as u32;
+        let wh = ((window.1 as u64 * sh as u64 + ch as u64 - 1) / ch as u64).max(1).min(sh as u64) as u32;
+
+        let center = ((sw - ww) as f64 / 2.0, (sh - wh) as f64 / 2.0);
+        let mut best = (center.0 as u32, center.1 as u32);
+        let mut best_score = -1f64;
+        let mut best_distance = 0f64;
+        for y in 0..(sh - wh + 1) {
+            for x in 0..(sw - ww + 1) {
+                let score = self.sum(x, y, ww, wh);
+                let distance = (x as f64 - center.0).abs() + (y as f64 - center.1).abs();
+                // equal scores are resolved in favor of the window closest to the center
+                if score > best_score || (score == best_score && distance < best_distance) {
+                    best = (x, y);
+                    best_score = score;
+                    best_distance = distance;
+                }
+            }
+        }
+        ((best.0 as f32 + ww as f32 / 2.0) / sw as f32, (best.1 as f32 + wh as f32 / 2.0) / sh as f32)
+    }
+
+    fn sum(&self, x: u32, y: u32, w: u32, h: u32) -> f64 {
+        let stride = self.sample_size.0 as usize + 1;
+        let (x0, y0, x1, y1) = (x as usize, y as usize, (x + w) as usize, (y + h) as usize);
+        self.integral[y1 * stride + x1] - self.integral[y0 * stride + x1] - self.integral[y1 * stride + x0] + self.integral[y0 * stride + x0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::Rgba;
+
+    /// Flat gray, with a checkerboard in the 16x16 square at (`x`, `y`).
+    fn content(x: u32, y: u32) -> RgbaImage {
+        RgbaImage::from_fn(64, 64, |px, py| {
+            let patch = px >= x && px < x + 16 && py >= y && py < y + 16;
+            let value = if patch && (px + py) % 2 == 0 { 255 } else { 128 };
+            Rgba { data: [value, value, value, 255] }
+        })
+    }
+
+    #[test]
+    fn detail() {
+        // the window is pulled off the center until it covers the whole patch
+        for &(patch, window) in &[((4, 40), (32, 32)), ((44, 8), (24, 64)), ((20, 24), (48, 20))] {
+            let (x, y) = Saliency::new(&content(patch.0, patch.1)).focal_point(window);
+            let (left, top) = (x * 64.0 - window.0 as f32 / 2.0, y * 64.0 - window.1 as f32 / 2.0);
+            assert!(left >= 0.0 && left <= patch.0 as f32 && left + window.0 as f32 >= patch.0 as f32 + 16.0, "{:?} {}", patch, x);
+            assert!(top >= 0.0 && top <= patch.1 as f32 && top + window.1 as f32 >= patch.1 as f32 + 16.0, "{:?} {}", patch, y);
+        }
+    }
+
+    #[test]
+    fn flat() {
+        let flat = RgbaImage::from_pixel(50, 30, Rgba { data: [90, 90, 90, 255] });
+        let mut saliency = Saliency::new(&flat);
+        for &window in &[(10, 10), (50, 10), (24, 30), (50, 30)] {
+            assert_eq!(saliency.focal_point(window), (0.5, 0.5), "{:?}", window);
+        }
+    }
+
+    #[test]
+    fn cached() {
+        let mut saliency = Saliency::new(&content(4, 40));
+        let point = saliency.focal_point((32, 32));
+        assert_eq!(saliency.cache.len(), 1);
+        // a window size seen before is looked up, not searched again
+        saliency.cache.insert((32, 32), (0.0, 1.0));
+        assert_eq!(saliency.focal_point((32, 32)), (0.0, 1.0));
+        assert_eq!(saliency.cache.len(), 1);
+        assert_ne!(point, (0.0, 1.0));
+    }
+
+    #[test]
+    fn cache_limit() {
+        let mut saliency = Saliency::new(&content(4, 40));
+        for width in 1..CACHE_LIMIT as u32 + 1 {
+            saliency.focal_point((width, 8));
+        }
+        assert_eq!(saliency.cache.len(), CACHE_LIMIT);
+        // the window sizes cached so far are dropped to make room
+        saliency.focal_point((40, 40));
+        assert_eq!(saliency.cache.len(), 1);
+        assert!(saliency.cache.contains_key(&(40, 40)));
+        saliency.focal_point((40, 40));
+        assert_eq!(saliency.cache.len(), 1);
+    }
+}
@@ -0,0 +1,175 @@
+//! Hand-off of frames produced on other threads, e.g. by a camera or a video decoder, to an image showing them live.
+//!
+//! There is a single slot between the producer and the image: a frame pushed before the UI thread has taken the
+//! previous one replaces it, so a slow UI drops frames instead of queueing them. The buffers of the frames shown are
+//! given back to the sink, so pushing frames of the same size does not allocate.
+
+use super::render::Renderer;
+use super::ImageSource;
+
+use image::RgbaImage;
+
+use std::sync::{Arc, Mutex};
+
+/// Producer side of a live image, see `Image::frame_sink`. Can be cloned and sent to any thread.
+#[derive(Clone)]
+pub struct FrameSink {
+    slot: Arc<Mutex<Slot>>,
+}
+
+/// Image side of a `FrameSink`, closing it when dropped.
+pub(crate) struct Receiver {
+    slot: Arc<Mutex<Slot>>,
+}
+
+#[derive(Default)]
+struct Slot {
+    pending: Option<RgbaImage>,
+    spare: Option<RgbaImage>,
+    pushed: u64,
+    dropped: u64,
+    closed: bool,
+}
+
+pub(crate) fn channel() -> (FrameSink, Receiver) {
+    let slot = Arc::new(Mutex::new(Slot::default()));
+    (FrameSink { slot: slot.clone() }, Receiver { slot: slot })
+}
+
+impl FrameSink {
+    /// Queues `frame` to be shown, replacing the frame queued before if it has not been shown yet.
+    /// Returns `false` if the image is gone or has been given another sink.
+    pub fn push(&self, frame: RgbaImage) -> bool {
+        let mut slot = self.slot.lock().unwrap();
+        if slot.closed {
+            return false;
+        }
+        if let Some(replaced) = slot.pending.take() {
+            slot.dropped += 1;
+            slot.spare = Some(replaced);
+        }
+        slot.pending = Some(frame);
+        slot.pushed += 1;
+        true
+    }
+    /// Same as `push`, copying the `width` x `height` RGBA bytes into a buffer of an earlier frame when there is one of the same size.
+    /// Returns `false` as well if `rgba` is not of that size.
+    pub fn push_raw(&self, width: u32, height: u32, rgba: &[u8]) -> bool {
+        if rgba.len() != width as usize * height as usize * 4 {
+            return false;
+        }
+        // copied with the slot unlocked, so the UI thread is not blocked meanwhile
+        let spare = self.slot.lock().unwrap().spare.take();
+        let mut frame = match spare {
+            Some(frame) if frame.dimensions() == (width, height) => frame,
+            _ => RgbaImage::new(width, height),
+        };
+        frame.copy_from_slice(rgba);
+        self.push(frame)
+    }
+    pub fn is_closed(&self) -> bool {
+        self.slot.lock().unwrap().closed
+    }
+    /// Amount of frames pushed so far.
+    pub fn pushed_frames(&self) -> u64 {
+        self.slot.lock().unwrap().pushed
+    }
+    /// Amount of frames replaced before being shown, because the UI thread fell behind.
+    pub fn dropped_frames(&self) -> u64 {
+        self.slot.lock().unwrap().dropped
+    }
+}
+
+impl Receiver {
+    /// The latest frame pushed, if there is a new one.
+    pub fn take(&self) -> Option<RgbaImage> {
+        self.slot.lock().unwrap().pending.take()
+    }
+    /// Gives the buffer of a frame no longer shown back to the producer.
+    pub fn recycle(&self, buffer: RgbaImage) {
+        self.slot.lock().unwrap().spare = Some(buffer);
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        let mut slot = self.slot.lock().unwrap();
+        slot.closed = true;
+        slot.pending = None;
+        slot.spare = None;
+    }
+}
+
+/// Makes `frame` the content of `renderer`, recycling the buffer of the previous content if nothing else shares it.
+/// Returns whether the content size has changed, so the backend cannot redraw the frame shown in place.
+pub(crate) fn show(renderer: &mut Renderer, receiver: &Receiver, frame: RgbaImage) -> bool {
+    let resized = renderer.content().map(ImageSource::dimensions) != Some(frame.dimensions());
+    if let Some(previous) = renderer.set_content(Some(ImageSource::new(frame))) {
+        if let Ok(buffer) = previous.into_pixels() {
+            receiver.recycle(buffer);
+        }
+    }
+    resized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::Rgba;
+
+    fn frame(value: u8) -> RgbaImage {
+        RgbaImage::from_pixel(2, 1, Rgba { data: [value; 4] })
+    }
+
+    #[test]
+    fn drops_frames() {
+        let (sink, receiver) = channel();
+        assert!(receiver.take().is_none());
+        assert!(sink.push(frame(1)));
+        assert!(sink.push(frame(2)));
+        // the second frame replaced the first one, which was never shown
+        assert_eq!(receiver.take().unwrap().into_raw(), frame(2).into_raw());
+        assert!(receiver.take().is_none());
+        assert_eq!((sink.pushed_frames(), sink.dropped_frames()), (2, 1));
+    }
+
+    #[test]
+    fn reuses_buffers() {
+        let (sink, receiver) = channel();
+        let mut renderer = Renderer::new(None);
+        let first = frame(1);
+        let buffer = first.as_ptr();
+        assert!(sink.push(first));
+        assert!(show(&mut renderer, &receiver, receiver.take().unwrap()));
+        assert!(sink.push(frame(2)));
+        assert!(!show(&mut renderer, &receiver, receiver.take().unwrap()));
+
+        // the buffer of the first frame, no longer shown, is written to again
+        assert!(sink.push_raw(2, 1, &[3; 8]));
+        let third = receiver.take().unwrap();
+        assert_eq!(third.as_ptr(), buffer);
+        assert_eq!(third.into_raw(), vec![3; 8]);
+
+        // unless the size changed
+        receiver.recycle(frame(4));
+        assert!(sink.push_raw(1, 1, &[5; 4]));
+        assert_eq!(receiver.take().unwrap().dimensions(), (1, 1));
+        assert!(!sink.push_raw(1, 1, &[5; 3]));
+    }
+
+    #[test]
+    fn closed() {
+        let (sink, receiver) = channel();
+        let other = sink.clone();
+        assert!(sink.push(frame(1)));
+        assert!(!other.is_closed());
+        drop(receiver);
+        assert!(sink.is_closed());
+        assert!(other.is_closed());
+        // pushing on is harmless
+        assert!(!sink.push(frame(2)));
+        assert!(!other.push_raw(2, 1, &[0; 8]));
+        assert_eq!(sink.pushed_frames(), 1);
+    }
+}
@@ -0,0 +1,219 @@
+//! Turning of the content before it is placed: the EXIF orientation, the flip and the rotation of an image.
+//!
+//! Quarter turns and flips move the pixels as they are, any other angle resamples them into the rotated bounds,
+//! leaving the corners transparent.
+
+use super::geometry::Rect;
+use super::pixels;
+use super::{Flip, Interpolation, Orientation};
+
+use image::{Rgba, RgbaImage};
+
+/// Linear map of the content around its center, in pixels with the y axis pointing down:
+/// `x' = m[0] * x + m[1] * y`, `y' = m[2] * x + m[3] * y`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    m: [f64; 4],
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform { m: [1.0, 0.0, 0.0, 1.0] };
+
+    /// Clockwise rotation by `degrees`, exact for the quarter turns.
+    pub fn rotation(degrees: f32) -> Transform {
+        let degrees = normalize(degrees);
+        if degrees % 90.0 == 0.0 {
+            return match (degrees / 90.0) as u32 {
+                1 => Transform { m: [0.0, -1.0, 1.0, 0.0] },
+                2 => Transform { m: [-1.0, 0.0, 0.0, -1.0] },
+                3 => Transform { m: [0.0, 1.0, -1.0, 0.0] },
+                _ => Transform::IDENTITY,
+            };
+        }
+        let (sin, cos) = (degrees as f64).to_radians().sin_cos();
+        Transform { m: [cos, -sin, sin, cos] }
+    }
+    pub fn flip(flip: Flip) -> Transform {
+        match flip {
+            Flip::None => Transform::IDENTITY,
+            Flip::Horizontal => Transform { m: [-1.0, 0.0, 0.0, 1.0] },
+            Flip::Vertical => Transform { m: [1.0, 0.0, 0.0, -1.0] },
+            Flip::Both => Transform { m: [-1.0, 0.0, 0.0, -1.0] },
+        }
+    }
+    /// Turns content stored in `orientation` upright.
+    pub fn orientation(orientation: Orientation) -> Transform {
+        match orientation {
+            Orientation::Normal => Transform::IDENTITY,
+            Orientation::FlipHorizontal => Transform::flip(Flip::Horizontal),
+            Orientation::Rotate180 => Transform::rotation(180.0),
+            Orientation::FlipVertical => Transform::flip(Flip::Vertical),
+            Orientation::Transpose => Transform::flip(Flip::Horizontal).then(Transform::rotation(270.0)),
+            Orientation::Rotate90 => Transform::rotation(90.0),
+            Orientation::Transverse => Transform::flip(Flip::Horizontal).then(Transform::rotation(90.0)),
+            Orientation::Rotate270 => Transform::rotation(270.0),
+        }
+    }
+    /// This transform followed by `next`.
+    pub fn then(self, next: Transform) -> Transform {
+        let (n, s) = (next.m, self.m);
+        Transform {
+            m: [n[0] * s[0] + n[1] * s[2], n[0] * s[1] + n[1] * s[3], n[2] * s[0] + n[3] * s[2], n[2] * s[1] + n[3] * s[3]],
+        }
+    }
+    pub fn is_identity(&self) -> bool {
+        *self == Transform::IDENTITY
+    }
+    /// Whether the pixels are only moved, not resampled.
+    pub fn is_lossless(&self) -> bool {
+        self.m.iter().all(|&m| m == 0.0 || m == 1.0 || m == -1.0)
+    }
+    pub fn matrix(&self) -> [f64; 4] {
+        self.m
+    }
+    /// Size of the bounds of content of `size` once turned, rounded up.
+    pub fn size(&self, size: (u32, u32)) -> (u32, u32) {
+        let (width, height) = (size.0 as f64, size.1 as f64);
+        // the tolerance keeps the float error of the sine and cosine from adding a pixel
+        let bound = |x: f64, y: f64| (x.abs() * width + y.abs() * height - 1e-6).ceil().max(0.0) as u32;
+        (bound(self.m[0], self.m[1]), bound(self.m[2], self.m[3]))
+    }
+    /// Where the point at `point` of content of `size` ends up within its turned bounds.
+    pub fn map_point(&self, point: (f64, f64), size: (u32, u32)) -> (f64, f64) {
+        let bounds = self.size(size);
+        let (x, y) = (point.0 - size.0 as f64 / 2.0, point.1 - size.1 as f64 / 2.0);
+        (self.m[0] * x + self.m[1] * y + bounds.0 as f64 / 2.0, self.m[2] * x + self.m[3] * y + bounds.1 as f64 / 2.0)
+    }
+    /// Where the `rect` part of content of `size` ends up within its turned bounds, `None` if the pixels are resampled.
+    pub fn map_rect(&self, rect: Rect, size: (u32, u32)) -> Option<Rect> {
+        if !self.is_lossless() {
+            return None;
+        }
+        let (x1, y1) = self.map_point((rect.x as f64, rect.y as f64), size);
+        let (x2, y2) = self.map_point(((rect.x + rect.width) as f64, (rect.y + rect.height) as f64), size);
+        let (left, top) = (x1.min(x2).round() as i32, y1.min(y2).round() as i32);
+        Some(Rect::new(left, top, x1.max(x2).round() as i32 - left, y1.max(y2).round() as i32 - top))
+    }
+    /// `content` turned into its bounds, sampled with `interpolation` unless the pixels are only moved.
+    /// The filters wider than the bilinear one sample as the bilinear one does.
+    pub fn apply(&self, content: &RgbaImage, interpolation: Interpolation) -> RgbaImage {
+        let size = content.dimensions();
+        let (width, height) = self.size(size);
+        let inverse = self.inverse();
+        // pixel centers of the bounds mapped back onto the content
+        let source = |x: u32, y: u32| {
+            let (x, y) = (x as f64 + 0.5 - width as f64 / 2.0, y as f64 + 0.5 - height as f64 / 2.0);
+            (inverse[0] * x + inverse[1] * y + size.0 as f64 / 2.0, inverse[2] * x + inverse[3] * y + size.1 as f64 / 2.0)
+        };
+        if self.is_lossless() {
+            return RgbaImage::from_fn(width, height, |x, y| {
+                let (sx, sy) = source(x, y);
+                *content.get_pixel(sx.floor() as u32, sy.floor() as u32)
+            });
+        }
+        if interpolation == Interpolation::Nearest {
+            return RgbaImage::from_fn(width, height, |x, y| {
+                let (sx, sy) = source(x, y);
+                pixel(content, sx.floor() as i64, sy.floor() as i64)
+            });
+        }
+        // filtered premultiplied, see `render::resize`
+        let mut premultiplied = content.clone();
+        pixels::premultiply(&mut premultiplied);
+        let mut frame = RgbaImage::from_fn(width, height, |x, y| {
+            let (sx, sy) = source(x, y);
+            let (sx, sy) = (sx - 0.5, sy - 0.5);
+            let (left, top) = (sx.floor(), sy.floor());
+            let (fx, fy) = (sx - left, sy - top);
+            let (left, top) = (left as i64, top as i64);
+            let mut sum = [0.0; 4];
+            for &(px, py, weight) in &[(left, top, (1.0 - fx) * (1.0 - fy)), (left + 1, top, fx * (1.0 - fy)), (left, top + 1, (1.0 - fx) * fy), (left + 1, top + 1, fx * fy)] {
+                let sample = pixel(&premultiplied, px, py);
+                for channel in 0..4 {
+                    sum[channel] += sample.data[channel] as f64 * weight;
+                }
+            }
+            Rgba { data: [sum[0].round() as u8, sum[1].round() as u8, sum[2].round() as u8, sum[3].round() as u8] }
+        });
+        pixels::unpremultiply(&mut frame);
+        frame
+    }
+    fn inverse(&self) -> [f64; 4] {
+        let m = self.m;
+        let det = m[0] * m[3] - m[1] * m[2];
+        [m[3] / det, -m[1] / det, -m[2] / det, m[0] / det]
+    }
+}
+
+/// `degrees` within 0..360.
+pub fn normalize(degrees: f32) -> f32 {
+    let degrees = degrees % 360.0;
+    if degrees < 0.0 {
+        degrees + 360.0
+    } else {
+        degrees
+    }
+}
+
+/// The pixel at (`x`, `y`), transparent outside of `content`.
+fn pixel(content: &RgbaImage, x: i64, y: i64) -> Rgba<u8> {
+    let (width, height) = content.dimensions();
+    if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+        Rgba { data: [0; 4] }
+    } else {
+        *content.get_pixel(x as u32, y as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarter_turns() {
+        assert_eq!(Transform::rotation(90.0).matrix(), [0.0, -1.0, 1.0, 0.0]);
+        assert_eq!(Transform::rotation(-90.0), Transform::rotation(270.0));
+        assert_eq!(Transform::rotation(450.0), Transform::rotation(90.0));
+        assert_eq!(Transform::rotation(90.0).then(Transform::rotation(90.0)), Transform::rotation(180.0));
+        assert_eq!(Transform::rotation(90.0).then(Transform::rotation(270.0)), Transform::IDENTITY);
+        assert_eq!(Transform::flip(Flip::Horizontal).then(Transform::flip(Flip::Vertical)), Transform::rotation(180.0));
+        assert!(Transform::rotation(360.0).is_identity());
+
+        let turned = Transform::rotation(30.0);
+        assert!(!turned.is_lossless());
+        assert_eq!(turned.map_rect(Rect::new(0, 0, 1, 1), (3, 2)), None);
+        // 3 * cos 30 + 2 * sin 30 by 3 * sin 30 + 2 * cos 30
+        assert_eq!(turned.size((3, 2)), (4, 4));
+    }
+
+    #[test]
+    fn orientations() {
+        let content = RgbaImage::from_fn(3, 2, |x, y| Rgba { data: [x as u8, y as u8, 0, 255] });
+        // where the pixel at (x, y) of the upright content is stored
+        let stored = |orientation: Orientation, x: u32, y: u32| match orientation {
+            Orientation::Normal => (x, y),
+            Orientation::FlipHorizontal => (2 - x, y),
+            Orientation::Rotate180 => (2 - x, 1 - y),
+            Orientation::FlipVertical => (x, 1 - y),
+            Orientation::Transpose => (y, x),
+            Orientation::Rotate90 => (y, 1 - x),
+            Orientation::Transverse => (2 - y, 1 - x),
+            Orientation::Rotate270 => (2 - y, x),
+        };
+        for value in 1..9 {
+            let orientation = Orientation::from_exif(value).unwrap();
+            let transform = Transform::orientation(orientation);
+            assert!(transform.is_lossless(), "{:?}", orientation);
+            let upright = transform.apply(&content, Interpolation::Bicubic);
+            let size = if value >= 5 { (2, 3) } else { (3, 2) };
+            assert_eq!(upright.dimensions(), size, "{:?}", orientation);
+            assert_eq!(transform.size((3, 2)), size, "{:?}", orientation);
+            for (x, y, pixel) in upright.enumerate_pixels() {
+                let (sx, sy) = stored(orientation, x, y);
+                assert_eq!(pixel, content.get_pixel(sx, sy), "{:?} at {}x{}", orientation, x, y);
+                let moved = transform.map_rect(Rect::new(sx as i32, sy as i32, 1, 1), (3, 2));
+                assert_eq!(moved, Some(Rect::new(x as i32, y as i32, 1, 1)), "{:?} at {}x{}", orientation, x, y);
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! SVG content, rasterized anew at the exact size it is shown at instead of scaling a raster of it.
+//!
+//! The parsed document shares its nodes through `Rc`, so it never leaves the thread that parsed it: the content keeps
+//! the SVG data, and each image parses it once into a `Tree` of its own the first time it draws it.
+
+use super::pixels;
+use super::ImageError;
+
+use flate2::read::GzDecoder;
+use image::RgbaImage;
+use resvg;
+use tiny_skia;
+use usvg;
+
+use std::io::Read;
+
+/// SVG data known to parse, together with its natural size in pixels.
+#[derive(Debug)]
+pub struct Vector {
+    svg: Vec<u8>,
+    size: (f64, f64),
+}
+
+/// Parsed SVG document, drawn on the thread it was parsed on.
+pub struct Tree(usvg::Tree);
+
+impl Vector {
+    /// The data and the document parsed from it.
+    pub fn parse(svg: &[u8]) -> Result<(Vector, Tree), ImageError> {
+        let tree = parse(svg)?;
+        let size = tree.svg_node().size;
+        let vector = Vector {
+            svg: svg.to_vec(),
+            size: (size.width(), size.height()),
+        };
+        Ok((vector, Tree(tree)))
+    }
+    /// The document parsed again from the data, for the calling thread.
+    pub fn tree(&self) -> Option<Tree> {
+        parse(&self.svg).ok().map(Tree)
+    }
+    /// Size of the picture, rounded up to whole pixels.
+    pub fn size(&self) -> (u32, u32) {
+        (self.size.0.ceil().max(1.0) as u32, self.size.1.ceil().max(1.0) as u32)
+    }
+}
+
+impl Tree {
+    /// Draws the picture mapped by `matrix` (`x' = matrix[0] * x + matrix[1] * y`, `y' = matrix[2] * x + matrix[3] * y`)
+    /// and then moved by `offset` into a transparent `width` x `height` raster.
+    pub fn rasterize(&self, matrix: [f64; 4], offset: (f64, f64), width: u32, height: u32) -> RgbaImage {
+        let pixmap = match tiny_skia::Pixmap::new(width, height) {
+            Some(mut pixmap) => {
+                let (sx, kx, ky, sy) = (matrix[0] as f32, matrix[1] as f32, matrix[2] as f32, matrix[3] as f32);
+                let transform = tiny_skia::Transform::from_row(sx, ky, kx, sy, offset.0 as f32, offset.1 as f32);
+                resvg::render(&self.0, usvg::FitTo::Original, transform, pixmap.as_mut());
+                pixmap
+            }
+            None => return RgbaImage::new(width, height),
+        };
+        let mut raster = RgbaImage::from_raw(width, height, pixmap.take()).expect("pixmap of another size");
+        pixels::unpremultiply(&mut raster);
+        raster
+    }
+}
+
+/// Whether `bytes` look like SVG, plain or compressed, as opposed to any other XML or compressed data.
+pub fn is_svg(bytes: &[u8]) -> bool {
+    let mut head = Vec::with_capacity(HEAD);
+    // gzip, see `usvg::Tree::from_data`, only as much of it inflated as is looked at
+    let head = if bytes.starts_with(&[0x1f, 0x8b]) {
+        match GzDecoder::new(bytes).take(HEAD as u64).read_to_end(&mut head) {
+            Ok(_) => &head[..],
+            Err(_) => return false,
+        }
+    } else {
+        &bytes[..bytes.len().min(HEAD)]
+    };
+    let head = String::from_utf8_lossy(head);
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+    head.starts_with('<') && head.contains("<svg")
+}
+
+/// Amount of bytes looked at for the SVG root.
+const HEAD: usize = 4096;
+
+fn parse(svg: &[u8]) -> Result<usvg::Tree, ImageError> {
+    usvg::Tree::from_data(svg, &usvg::Options::default().to_ref()).map_err(|e| ImageError::Decode(format!("invalid SVG: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use std::io::Write;
+
+    /// Left half red, right half transparent.
+    const SVG: &[u8] = br#"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="2"><rect width="2" height="2" fill="red"/></svg>"#;
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn two_sizes() {
+        let (vector, tree) = Vector::parse(SVG).unwrap();
+        assert_eq!(vector.size(), (4, 2));
+        for &scale in &[1.0, 3.0] {
+            let (width, height) = ((4.0 * scale) as u32, (2.0 * scale) as u32);
+            let raster = tree.rasterize([scale, 0.0, 0.0, scale], (0.0, 0.0), width, height);
+            assert_eq!(raster.dimensions(), (width, height));
+            for (x, y, pixel) in raster.enumerate_pixels() {
+                let expected = if x < width / 2 { [255, 0, 0, 255] } else { [0; 4] };
+                assert_eq!(pixel.data, expected, "{} at {}x{}", scale, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn compressed() {
+        assert!(is_svg(SVG));
+        assert!(is_svg(&gzip(SVG)));
+        assert!(!is_svg(b"<?xml version=\"1.0\"?><feed/>"));
+        assert!(!is_svg(&gzip(b"<?xml version=\"1.0\"?><feed/>")));
+        assert!(!is_svg(&gzip(&[0; 64])));
+        assert!(!is_svg(&[0x1f, 0x8b, 8]));
+        let (vector, _) = Vector::parse(&gzip(SVG)).unwrap();
+        assert_eq!(vector.size(), (4, 2));
+        assert!(vector.tree().is_some());
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        fn shared<T: Send + Sync>() {}
+        shared::<Vector>();
+    }
+}